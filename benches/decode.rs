@@ -0,0 +1,66 @@
+//! Decode throughput for every data type on a 512 byte packet, run with `cargo bench`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use csv::StringRecord;
+use udp_to_csv::{decode_packet, value_count, CsvRows, CsvTokens, DataType, DecodeOptions};
+
+const PACKET_LEN: usize = 512;
+
+const DATA_TYPES: [DataType; 14] = [
+    DataType::Bool, DataType::U8, DataType::U16, DataType::U24, DataType::U32, DataType::U64,
+    DataType::I8, DataType::I16, DataType::I24, DataType::I32, DataType::I64,
+    DataType::F32, DataType::F64, DataType::Hex,
+];
+
+fn packet() -> Vec<u8> {
+    // bytes below 0x7f never make a float NaN or infinite
+    (0..PACKET_LEN).map(|i| (i % 61) as u8).collect()
+}
+
+fn decode(c: &mut Criterion) {
+    let packet = packet();
+    let options = DecodeOptions::default();
+    let mut group = c.benchmark_group("decode");
+    for data_type in DATA_TYPES {
+        group.throughput(Throughput::Elements(value_count(packet.len(), &[data_type], &options) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(data_type), &data_type, |b, &data_type| {
+            b.iter(|| decode_packet(black_box(&packet), &[data_type], &options).unwrap());
+        });
+    }
+    group.finish();
+}
+
+/// Turning decoded values into a csv row: before, with a `String` per value and a new csv writer
+/// per row, and now with the [`CsvRows`] the writer uses.
+fn format(c: &mut Criterion) {
+    let packet = packet();
+    let options = DecodeOptions::default();
+    let mut group = c.benchmark_group("format");
+    for data_type in [DataType::U8, DataType::I32, DataType::F64] {
+        let values = decode_packet(&packet, &[data_type], &options).unwrap();
+        group.throughput(Throughput::Elements(values.len() as u64));
+        group.bench_with_input(BenchmarkId::new("string_per_value", data_type), &values, |b, values| {
+            b.iter(|| {
+                let record: StringRecord = values.iter().map(ToString::to_string).collect();
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer.write_record(&record).unwrap();
+                String::from_utf8(writer.into_inner().unwrap()).unwrap()
+            });
+        });
+        let mut rows = CsvRows::new(csv::WriterBuilder::new(), "\n", CsvTokens::default());
+        let mut out = String::with_capacity(PACKET_LEN * 4);
+        group.bench_with_input(BenchmarkId::new("csv_rows", data_type), &values, |b, values| {
+            b.iter(|| {
+                out.clear();
+                rows.write_row([], values, [], &mut out);
+                black_box(&out);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, format);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! Receive path on a tokio runtime, enabled by the `async` feature.
+//!
+//! Only receiving runs on the runtime, the writer does blocking file I/O and runs on its own
+//! thread as in the sync path.
+
+use std::net::UdpSocket;
+use std::sync::mpsc::SyncSender;
+
+use socket2::SockRef;
+use tokio::io::Interest;
+
+use crate::{forward, recv_datagram, recv_error, report_skipped, report_truncation, spawn_writer, split_datagram, try_send, writer_panicked, Cli, KernelDrops, Limits, OutputFile, Packet, POLL_INTERVAL};
+
+/// Captures from `socket` until a limit is reached, then writes out what is still pending.
+pub fn capture_udp(socket: &UdpSocket, output_file: Option<OutputFile>, options: Cli, kernel_drops: KernelDrops, limits: &Limits, buffer_size: usize) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    // the clone shares the socket so the caller can still leave a multicast group afterwards
+    let socket = socket.try_clone()?;
+    socket.set_nonblocking(true)?;
+
+    let (tx, writer_thread) = spawn_writer(output_file, options, kernel_drops);
+    let result = runtime.block_on(async {
+        let socket = tokio::net::UdpSocket::from_std(socket)?;
+        receive(&socket, tx, limits, buffer_size).await;
+        Ok(())
+    });
+    if let Err(panic) = writer_thread.join() {
+        writer_panicked(panic);
+    }
+    result
+}
+
+async fn receive(socket: &tokio::net::UdpSocket, tx: SyncSender<Packet>, limits: &Limits, buffer_size: usize) {
+    let port = socket.local_addr().map_or(0, |address| address.port());
+    let mut buffer = vec![0u8; buffer_size];
+    // truncation is reported once per capture, not for every datagram
+    let mut truncation_reported = false;
+    let mut partial_reported = false;
+    let mut idle = limits.idle();
+    let mut throttle = limits.throttle();
+    let mut deframer = limits.deframer();
+    let mut errors = limits.recv_errors();
+    while !limits.reached() {
+        let timeout = limits.read_timeout().unwrap_or(POLL_INTERVAL);
+        let recv = socket.async_io(Interest::READABLE, || recv_datagram(&SockRef::from(socket), &mut buffer));
+        match tokio::time::timeout(timeout, recv).await {
+            Err(_) => {
+                if idle.check() {
+                    break;
+                }
+            },
+            Ok(Err(e)) => match recv_error(&mut errors, &e, limits) {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => break,
+            },
+            Ok(Ok(received)) => {
+                errors.packet();
+                let source = received.source;
+                if !truncation_reported {
+                    truncation_reported = report_truncation(received.len, received.datagram_len, buffer.len(), source);
+                }
+                idle.packet();
+                let records = split_datagram(&mut deframer, &buffer[0..received.len], source, &mut partial_reported);
+                if !forward(records, source, port, received.kernel_time, &mut throttle, limits, |packet| try_send(&tx, packet, limits)) {
+                    break;
+                }
+            },
+        };
+    }
+    report_skipped(&deframer);
+}
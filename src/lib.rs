@@ -0,0 +1,1005 @@
+//! Decoding of raw packet payloads into values, and writing those values as csv rows.
+
+use clap::ValueEnum;
+use std::fmt;
+use std::fmt::Write as _;
+
+use std::io::Cursor;
+use std::time::Duration;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DataType {
+    Bool,
+    U8,
+    U16,
+    /// three bytes, decoded into a u32
+    U24,
+    U32,
+    U64,
+    I8,
+    I16,
+    /// three bytes, sign-extended into an i32
+    I24,
+    I32,
+    I64,
+    F32,
+    F64,
+    /// the rest of the packet as one hex string
+    Hex,
+    /// the rest of the packet as utf-8 text, invalid sequences become U+FFFD
+    Ascii,
+}
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            DataType::Bool => "bool",
+            DataType::U8 =>  "u8",
+            DataType::U16 => "u16",
+            DataType::U24 => "u24",
+            DataType::U32 => "u32",
+            DataType::U64 => "u64",
+            DataType::I8 =>  "i8",
+            DataType::I16 => "i16",
+            DataType::I24 => "i24",
+            DataType::I32 => "i32",
+            DataType::I64 => "i64",
+            DataType::F32 => "f32",
+            DataType::F64 => "f64",
+            DataType::Hex => "hex",
+            DataType::Ascii => "ascii",
+        })
+    }
+}
+impl std::str::FromStr for DataType {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "BOOLEAN" => Ok(DataType::Bool),
+            "BOOL" =>    Ok(DataType::Bool),
+            "U8" =>  Ok(DataType::U8),
+            "U16" => Ok(DataType::U16),
+            "U24" => Ok(DataType::U24),
+            "U32" => Ok(DataType::U32),
+            "U64" => Ok(DataType::U64),
+            "I8" =>  Ok(DataType::I8),
+            "I16" => Ok(DataType::I16),
+            "I24" => Ok(DataType::I24),
+            "I32" => Ok(DataType::I32),
+            "I64" => Ok(DataType::I64),
+            "F32" => Ok(DataType::F32),
+            "F64" => Ok(DataType::F64),
+            "HEX" => Ok(DataType::Hex),
+            "ASCII" => Ok(DataType::Ascii),
+            "UTF8" =>  Ok(DataType::Ascii),
+            _ => Err("invalid datatype"),
+        }
+    }
+}
+
+impl DataType {
+    /// Number of bytes a single value occupies in a packet, 0 for types that take the rest of it.
+    pub fn width(&self) -> usize {
+        match self {
+            DataType::Hex | DataType::Ascii => 0,
+            DataType::Bool | DataType::U8 | DataType::I8 => 1,
+            DataType::U16 | DataType::I16 => 2,
+            DataType::U24 | DataType::I24 => 3,
+            DataType::U32 | DataType::I32 | DataType::F32 => 4,
+            DataType::U64 | DataType::I64 | DataType::F64 => 8,
+        }
+    }
+
+    /// Whether a value takes all remaining bytes of the packet instead of a fixed number.
+    pub fn takes_rest(&self) -> bool {
+        matches!(self, DataType::Hex | DataType::Ascii)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Endianness {
+    Big,
+    Little,
+    /// guessed from the first packets with [`detect_endianness`], decoded as big until then
+    Auto,
+}
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Endianness::Big =>    "big",
+            Endianness::Little => "little",
+            Endianness::Auto =>   "auto",
+        })
+    }
+}
+impl std::str::FromStr for Endianness {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "BIG" =>    Ok(Endianness::Big),
+            "NETWORK" => Ok(Endianness::Big),
+            "LITTLE" => Ok(Endianness::Little),
+            "AUTO" =>   Ok(Endianness::Auto),
+            _ => Err("invalid endianness"),
+        }
+    }
+}
+
+/// Order in which the bits of a `Bool` byte become values, independent of the [`Endianness`]
+/// which only orders the bytes of wider values.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BitOrder {
+    Lsb,
+    Msb,
+}
+impl fmt::Display for BitOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            BitOrder::Lsb => "lsb",
+            BitOrder::Msb => "msb",
+        })
+    }
+}
+impl std::str::FromStr for BitOrder {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LSB" => Ok(BitOrder::Lsb),
+            "MSB" => Ok(BitOrder::Msb),
+            _ => Err("invalid bit order"),
+        }
+    }
+}
+
+/// How records are delimited within a datagram or a stream.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Framing {
+    /// every datagram or read is one record
+    None,
+    U16Length,
+    U32Length,
+}
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Framing::None =>      "none",
+            Framing::U16Length => "u16-length",
+            Framing::U32Length => "u32-length",
+        })
+    }
+}
+impl std::str::FromStr for Framing {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "NONE" =>       Ok(Framing::None),
+            "U16-LENGTH" => Ok(Framing::U16Length),
+            "U32-LENGTH" => Ok(Framing::U32Length),
+            _ => Err("invalid framing"),
+        }
+    }
+}
+
+/// Wait after the first of several receive errors in a row.
+pub const RECV_ERROR_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest wait after a receive error.
+pub const MAX_RECV_ERROR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Backs off from receive errors that keep coming and gives up after `max` of them in a row.
+pub struct RecvErrors {
+    max: u32,
+    in_a_row: u32,
+    /// wait after the next error
+    interval: Duration,
+}
+impl RecvErrors {
+    pub fn new(max: u32) -> Self {
+        RecvErrors { max, in_a_row: 0, interval: RECV_ERROR_INTERVAL }
+    }
+
+    /// A packet arrived, the next error starts over.
+    pub fn packet(&mut self) {
+        self.in_a_row = 0;
+        self.interval = RECV_ERROR_INTERVAL;
+    }
+
+    /// Counts an error and returns how long to wait before receiving again, doubling from
+    /// [`RECV_ERROR_INTERVAL`] up to [`MAX_RECV_ERROR_INTERVAL`], or `None` once `max` errors
+    /// came in a row.
+    pub fn error(&mut self) -> Option<Duration> {
+        self.in_a_row += 1;
+        if self.in_a_row >= self.max {
+            return None;
+        }
+        let interval = self.interval;
+        self.interval = (interval * 2).min(MAX_RECV_ERROR_INTERVAL);
+        Some(interval)
+    }
+
+    pub fn in_a_row(&self) -> u32 {
+        self.in_a_row
+    }
+}
+
+/// Splits received bytes into records that are preceded by their length in network byte order,
+/// that start with a sync word or that all have the same length.
+pub struct Deframer {
+    framing: Framing,
+    /// longer records are taken for a corrupt length prefix
+    max_len: usize,
+    sync_word: Option<Vec<u8>>,
+    /// length of every record of unframed data
+    record_len: Option<usize>,
+    buffer: Vec<u8>,
+    /// a bad length prefix was reported and bytes are skipped until a plausible one
+    resyncing: bool,
+    /// the buffer starts with a sync word
+    synced: bool,
+    /// bytes dropped in front of sync words so far
+    skipped: usize,
+}
+impl Deframer {
+    pub fn new(framing: Framing, max_len: usize) -> Self {
+        Deframer { framing, max_len, sync_word: None, record_len: None, buffer: Vec::new(), resyncing: false, synced: false, skipped: 0 }
+    }
+
+    /// Cuts unframed data into records of `record_len` bytes instead of taking every datagram
+    /// or read as one, the bytes left at the end of a datagram are its last, shorter record.
+    pub fn with_record_len(mut self, record_len: usize) -> Self {
+        self.record_len = Some(record_len).filter(|&record_len| record_len > 0);
+        self
+    }
+
+    /// Starts every record at `sync_word` instead of relying on the framing, a record runs up
+    /// to the next sync word or the end of its datagram. Bytes before the first sync word are dropped.
+    pub fn with_sync_word(mut self, sync_word: Vec<u8>) -> Self {
+        self.sync_word = Some(sync_word).filter(|sync_word| !sync_word.is_empty());
+        self
+    }
+
+    /// Adds `bytes` and returns the records they complete; without framing that's `bytes` itself.
+    ///
+    /// A length over `max_len` is logged and skipped one byte at a time until the next length
+    /// that fits, so a stream that got out of step finds the record boundaries again.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        if let Some(sync_word) = self.sync_word.take() {
+            self.buffer.extend_from_slice(bytes);
+            let records = self.split_at_sync_words(&sync_word);
+            self.sync_word = Some(sync_word);
+            return records;
+        }
+        let prefix_len = match self.framing {
+            Framing::None => return self.split_unframed(bytes),
+            Framing::U16Length => 2,
+            Framing::U32Length => 4,
+        };
+        self.buffer.extend_from_slice(bytes);
+        let mut records = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start >= prefix_len {
+            let prefix = &self.buffer[start..start + prefix_len];
+            let len = match self.framing {
+                Framing::U16Length => BigEndian::read_u16(prefix) as usize,
+                _ => BigEndian::read_u32(prefix) as usize,
+            };
+            if len > self.max_len {
+                if !self.resyncing {
+                    log::warn!("record length {len} is over the limit of {} bytes, skipping ahead to the next plausible length", self.max_len);
+                    self.resyncing = true;
+                }
+                start += 1;
+                continue;
+            }
+            let end = start + prefix_len + len;
+            if end > self.buffer.len() {
+                break;
+            }
+            self.resyncing = false;
+            records.push(self.buffer[start + prefix_len..end].to_vec());
+            start = end;
+        }
+        self.buffer.drain(..start);
+        records
+    }
+
+    fn split_unframed(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let Some(record_len) = self.record_len else {
+            return vec![bytes.to_vec()];
+        };
+        self.buffer.extend_from_slice(bytes);
+        let whole = self.buffer.len() / record_len * record_len;
+        let records = self.buffer[..whole].chunks(record_len).map(<[u8]>::to_vec).collect();
+        self.buffer.drain(..whole);
+        records
+    }
+
+    fn split_at_sync_words(&mut self, sync_word: &[u8]) -> Vec<Vec<u8>> {
+        let find = |buffer: &[u8], from: usize| {
+            buffer.get(from..)?.windows(sync_word.len()).position(|window| window == sync_word).map(|i| from + i)
+        };
+        if !self.synced {
+            match find(&self.buffer, 0) {
+                Some(start) => {
+                    self.skipped += start;
+                    self.buffer.drain(..start);
+                    self.synced = true;
+                },
+                None => {
+                    // the end could be the first part of a sync word
+                    let keep = (sync_word.len() - 1).min(self.buffer.len());
+                    let drop = self.buffer.len() - keep;
+                    self.skipped += drop;
+                    self.buffer.drain(..drop);
+                    return Vec::new();
+                },
+            }
+        }
+        let mut records = Vec::new();
+        while let Some(next) = find(&self.buffer, sync_word.len()) {
+            records.push(self.buffer[sync_word.len()..next].to_vec());
+            self.buffer.drain(..next);
+        }
+        records
+    }
+
+    /// Ends a datagram and returns the record its end completes, which is the one after the last
+    /// sync word or the shorter last one of a record length, along with the number of bytes of
+    /// an incomplete record that are dropped.
+    pub fn end_datagram(&mut self) -> (Option<Vec<u8>>, usize) {
+        let rest = std::mem::take(&mut self.buffer);
+        self.resyncing = false;
+        match &self.sync_word {
+            Some(sync_word) if self.synced => {
+                self.synced = false;
+                (Some(rest[sync_word.len()..].to_vec()), 0)
+            },
+            Some(_) => {
+                self.skipped += rest.len();
+                (None, 0)
+            },
+            None if self.record_len.is_some() && !rest.is_empty() => (Some(rest), 0),
+            None => (None, rest.len()),
+        }
+    }
+
+    /// Number of bytes dropped in front of sync words so far.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+/// Checksum at the end of each record.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Checksum {
+    None,
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF
+    Crc16,
+    /// CRC-32 as used by zlib and ethernet
+    Crc32,
+}
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Checksum::None =>  "none",
+            Checksum::Crc16 => "crc16",
+            Checksum::Crc32 => "crc32",
+        })
+    }
+}
+impl std::str::FromStr for Checksum {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "NONE" =>  Ok(Checksum::None),
+            "CRC16" => Ok(Checksum::Crc16),
+            "CRC32" => Ok(Checksum::Crc32),
+            _ => Err("invalid checksum"),
+        }
+    }
+}
+
+impl Checksum {
+    /// Number of bytes the checksum takes up.
+    pub fn width(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// Splits the checksum off the end of `record` and returns the payload in front of it if it
+    /// matches, `None` if it doesn't or the record is too short to hold one. The checksum is read
+    /// in the byte order of `endianness`, big for auto.
+    pub fn verify(self, record: &[u8], endianness: Endianness) -> Option<&[u8]> {
+        let payload_len = record.len().checked_sub(self.width())?;
+        let (payload, checksum) = record.split_at(payload_len);
+        let matches = match (self, endianness) {
+            (Checksum::None, _) => true,
+            (Checksum::Crc16, Endianness::Little) => LittleEndian::read_u16(checksum) == crc16(payload),
+            (Checksum::Crc16, _) => BigEndian::read_u16(checksum) == crc16(payload),
+            (Checksum::Crc32, Endianness::Little) => LittleEndian::read_u32(checksum) == crc32(payload),
+            (Checksum::Crc32, _) => BigEndian::read_u32(checksum) == crc32(payload),
+        };
+        matches.then_some(payload)
+    }
+}
+
+/// CRC-16/CCITT-FALSE of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-32 (IEEE, reflected) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// What happens to a packet with a numeric value outside `filter_min`/`filter_max`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum FilterMode {
+    DropRow,
+    Blank,
+}
+impl fmt::Display for FilterMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            FilterMode::DropRow => "drop-row",
+            FilterMode::Blank =>   "blank",
+        })
+    }
+}
+impl std::str::FromStr for FilterMode {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "DROP-ROW" => Ok(FilterMode::DropRow),
+            "BLANK" =>    Ok(FilterMode::Blank),
+            _ => Err("invalid filter mode"),
+        }
+    }
+}
+
+/// How values are laid out in a packet, apart from their types.
+#[derive(Clone, Copy)]
+pub struct DecodeOptions {
+    /// byte order of multi-byte values
+    pub endianness: Endianness,
+    /// number of low bits of a `Bool` byte that are emitted, 1 to 8
+    pub bool_bits: u8,
+    /// whether bit 0 or the highest emitted bit comes first, `endianness` doesn't apply to bits
+    pub bool_order: BitOrder,
+    /// number of fractional bits of integer values in Q format, applied before `scale`
+    pub fixed_point: Option<u8>,
+    /// factor numeric values are multiplied with
+    pub scale: Option<f64>,
+    /// added to numeric values after scaling
+    pub offset: Option<f64>,
+    /// separate the bytes of a `Hex` value with spaces
+    pub hex_spaced: bool,
+    /// numeric values below this, after scaling, are filtered
+    pub filter_min: Option<f64>,
+    /// numeric values above this, after scaling, are filtered
+    pub filter_max: Option<f64>,
+    /// whether a filtered value drops its packet or is left blank
+    pub filter_mode: FilterMode,
+    /// complete the last repetition of the schema with [`Value::Missing`] if the packet ends
+    /// before it, so every row has a multiple of the values of one record
+    pub pad_records: bool,
+    /// decoding of a packet stops after this many values, see [`value_count`]
+    pub max_values: Option<usize>,
+}
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            endianness: Endianness::Big,
+            bool_bits: 8,
+            bool_order: BitOrder::Lsb,
+            fixed_point: None,
+            scale: None,
+            offset: None,
+            hex_spaced: false,
+            filter_min: None,
+            filter_max: None,
+            filter_mode: FilterMode::DropRow,
+            pad_records: false,
+            max_values: None,
+        }
+    }
+}
+impl DecodeOptions {
+    /// Number of values a single field of `data_type` decodes to.
+    pub fn values_per_field(&self, data_type: DataType) -> usize {
+        match data_type {
+            DataType::Bool => self.bool_bits as usize,
+            _ => 1,
+        }
+    }
+
+    /// Data type of the value at `index` of a decoded packet, with `schema` repeating as in
+    /// [`decode_packet`].
+    pub fn value_type(&self, schema: &[DataType], index: usize) -> DataType {
+        schema.iter()
+            .flat_map(|&data_type| std::iter::repeat_n(data_type, self.values_per_field(data_type)))
+            .cycle()
+            .nth(index)
+            .expect("the schema has at least one data type")
+    }
+}
+
+/// A single decoded value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// one bit of a `Bool` field
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    /// also every value that was scaled, offset or read in Q format
+    F64(f64),
+    /// hex digits of a `Hex` field, separated by spaces if `hex_spaced` is set
+    Hex(String),
+    /// an `Ascii` field
+    Text(String),
+    /// a numeric value outside the filter range, written as an empty field
+    Filtered,
+    /// a value the packet ended before, see `pad_records`
+    Missing,
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{}", u8::from(*value)),
+            Value::U8(value) =>  write!(f, "{value}"),
+            Value::U16(value) => write!(f, "{value}"),
+            Value::U32(value) => write!(f, "{value}"),
+            Value::U64(value) => write!(f, "{value}"),
+            Value::I8(value) =>  write!(f, "{value}"),
+            Value::I16(value) => write!(f, "{value}"),
+            Value::I32(value) => write!(f, "{value}"),
+            Value::I64(value) => write!(f, "{value}"),
+            Value::F32(value) => write!(f, "{value}"),
+            Value::F64(value) => write!(f, "{value}"),
+            Value::Hex(digits) => f.write_str(digits),
+            Value::Text(text) =>  f.write_str(text),
+            Value::Filtered | Value::Missing => Ok(()),
+        }
+    }
+}
+impl Value {
+    /// The value as a number, `None` for text and filtered values.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Bool(value) => Some(f64::from(u8::from(value))),
+            Value::U8(value) =>  Some(value as f64),
+            Value::U16(value) => Some(value as f64),
+            Value::U32(value) => Some(value as f64),
+            Value::U64(value) => Some(value as f64),
+            Value::I8(value) =>  Some(value as f64),
+            Value::I16(value) => Some(value as f64),
+            Value::I32(value) => Some(value as f64),
+            Value::I64(value) => Some(value as f64),
+            Value::F32(value) => Some(value as f64),
+            Value::F64(value) => Some(value),
+            Value::Hex(_) | Value::Text(_) | Value::Filtered | Value::Missing => None,
+        }
+    }
+}
+
+/// What csv writes for values that have no digits of their own.
+#[derive(Clone)]
+pub struct CsvTokens {
+    /// a filtered or missing value
+    pub null: String,
+    pub nan: String,
+    pub inf: String,
+    pub neg_inf: String,
+}
+impl Default for CsvTokens {
+    fn default() -> Self {
+        CsvTokens { null: String::new(), nan: "NaN".to_owned(), inf: "inf".to_owned(), neg_inf: "-inf".to_owned() }
+    }
+}
+impl CsvTokens {
+    /// Token written for a float that is NaN or infinite, `None` for every other value.
+    /// The default tokens read back as floats, so quoting non-numeric fields leaves them unquoted.
+    pub fn float_token(&self, value: &Value) -> Option<&str> {
+        let float = match *value {
+            Value::F32(value) => value as f64,
+            Value::F64(value) => value,
+            _ => return None,
+        };
+        if float.is_nan() {
+            Some(&self.nan)
+        } else if float == f64::INFINITY {
+            Some(&self.inf)
+        } else if float == f64::NEG_INFINITY {
+            Some(&self.neg_inf)
+        } else {
+            None
+        }
+    }
+
+    /// Converts a decoded value to a csv field.
+    pub fn field(&self, value: &Value) -> String {
+        match value {
+            Value::Filtered | Value::Missing => self.null.clone(),
+            value => self.float_token(value).map_or_else(|| value.to_string(), str::to_owned),
+        }
+    }
+}
+
+/// Output the csv writer of [`CsvRows`] collects before its buffer is emptied.
+const CSV_RECYCLE_LEN: usize = 64 * 1024;
+
+/// Serializes rows with a csv writer that is kept for the whole capture, one built for every row
+/// would allocate its buffer each time.
+pub struct CsvRows {
+    builder: csv::WriterBuilder,
+    writer: csv::Writer<Vec<u8>>,
+    /// bytes at the start of the writer's output that were already handed out
+    taken: usize,
+    /// a value formatted for its field, reused for every value
+    field: String,
+    /// written for an empty row, the writer's terminator ends every other one
+    line_ending: &'static str,
+    tokens: CsvTokens,
+}
+impl CsvRows {
+    pub fn new(builder: csv::WriterBuilder, line_ending: &'static str, tokens: CsvTokens) -> Self {
+        let writer = builder.from_writer(Vec::new());
+        CsvRows { builder, writer, taken: 0, field: String::new(), line_ending, tokens }
+    }
+
+    pub fn tokens(&self) -> &CsvTokens {
+        &self.tokens
+    }
+
+    /// Appends a row of `fields` to `out` including its line break, quoted as the builder says.
+    pub fn write_record<T: AsRef<[u8]>>(&mut self, fields: impl IntoIterator<Item = T>, out: &mut String) {
+        let mut empty = true;
+        for field in fields {
+            self.writer.write_field(field).expect("writing to memory can't fail");
+            empty = false;
+        }
+        self.end_row(empty, out);
+    }
+
+    /// Appends the row of a packet between the already formatted `leading` and `trailing` fields,
+    /// a filtered or missing value and a float that isn't finite are written as their token.
+    pub fn write_row<'a>(&mut self, leading: impl IntoIterator<Item = &'a str>, values: &[Value], trailing: impl IntoIterator<Item = &'a str>, out: &mut String) {
+        let mut empty = values.is_empty();
+        for field in leading {
+            self.writer.write_field(field).expect("writing to memory can't fail");
+            empty = false;
+        }
+        for value in values {
+            let field = match (value, self.tokens.float_token(value)) {
+                (Value::Filtered | Value::Missing, _) => &self.tokens.null,
+                (_, Some(token)) => token,
+                (value, None) => {
+                    self.field.clear();
+                    write!(self.field, "{value}").expect("writing to a String can't fail");
+                    &self.field
+                },
+            };
+            self.writer.write_field(field).expect("writing to memory can't fail");
+        }
+        for field in trailing {
+            self.writer.write_field(field).expect("writing to memory can't fail");
+            empty = false;
+        }
+        self.end_row(empty, out);
+    }
+
+    fn end_row(&mut self, empty: bool, out: &mut String) {
+        // the csv writer turns an empty record into `""`, an empty datagram stays an empty row
+        if empty {
+            out.push_str(self.line_ending);
+            return;
+        }
+        self.writer.write_record(None::<&[u8]>).expect("writing to memory can't fail");
+        self.writer.flush().expect("writing to memory can't fail");
+        let output = self.writer.get_ref();
+        out.push_str(std::str::from_utf8(&output[self.taken..]).expect("a record of strings stays valid utf-8"));
+        self.taken = output.len();
+        if self.taken >= CSV_RECYCLE_LEN {
+            let writer = std::mem::replace(&mut self.writer, self.builder.from_writer(Vec::new()));
+            let mut output = writer.into_inner().expect("writing to memory can't fail");
+            output.clear();
+            self.writer = self.builder.from_writer(output);
+            self.taken = 0;
+        }
+    }
+}
+
+/// Why a packet could not be decoded completely.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// the packet ends inside a value of `expected` bytes, only `got` are left
+    Truncated { expected: usize, got: usize },
+    Io(std::io::Error),
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { expected, got } => write!(f, "packet ends in {got} bytes of a {expected} byte value"),
+            DecodeError::Io(e) => write!(f, "error while parsing: {e}"),
+        }
+    }
+}
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Truncated { .. } => None,
+            DecodeError::Io(e) => Some(e),
+        }
+    }
+}
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+/// Decodes `bytes` by reading one value of each type in `schema` in turn, repeating the
+/// schema until the packet is exhausted.
+///
+/// A packet may end after any whole value, so values of an incomplete last repetition of the
+/// schema are fine, but ending inside a value is an error. A `Bool` expands to one value per bit,
+/// a `Hex` or `Ascii` takes the rest of the packet.
+///
+/// Numeric values outside `filter_min`/`filter_max` are [`Value::Filtered`], see [`decode_row`]
+/// to drop the whole packet instead.
+pub fn decode_packet(bytes: &[u8], schema: &[DataType], options: &DecodeOptions) -> Result<Vec<Value>, DecodeError> {
+    let row = decode_values(bytes, schema, options);
+    match row.error {
+        Some(e) => Err(e),
+        None => Ok(row.values),
+    }
+}
+
+/// Decodes a packet like [`decode_packet`], but keeps the values in front of a truncated one
+/// and returns `None` if a value was filtered and the filter mode is `DropRow`.
+pub fn decode_row(bytes: &[u8], schema: &[DataType], options: &DecodeOptions) -> Option<Vec<Value>> {
+    let row = decode_values(bytes, schema, options);
+    match (row.filtered, options.filter_mode) {
+        (true, FilterMode::DropRow) => None,
+        _ => Some(row.values),
+    }
+}
+
+/// Values decoded from a packet so far.
+#[derive(Default)]
+struct Row {
+    values: Vec<Value>,
+    /// a numeric value was outside the filter range and left blank
+    filtered: bool,
+    /// why decoding stopped before the end of the packet
+    error: Option<DecodeError>,
+}
+impl Row {
+    /// Adds the values of the fields of `rest` as missing if `pad_records` is set.
+    fn pad(&mut self, rest: &[DataType], options: &DecodeOptions) {
+        if options.pad_records {
+            let missing: usize = rest.iter().map(|&data_type| options.values_per_field(data_type)).sum();
+            self.values.extend(std::iter::repeat_n(Value::Missing, missing));
+        }
+    }
+
+    /// Pushes an integer, as the real number it encodes if it is in Q format.
+    fn push_integer(&mut self, value: Value, options: &DecodeOptions) {
+        match (options.fixed_point, value.as_f64()) {
+            (Some(frac_bits), Some(as_f64)) => self.push_number(Value::F64(as_f64 / 2f64.powi(frac_bits.into())), options),
+            _ => self.push_number(value, options),
+        }
+    }
+
+    /// Pushes a numeric value, applying `scale` and `offset` if either is set, filtered if the
+    /// result is outside the filter range.
+    ///
+    /// A scaled value is an `f64` printed with the shortest representation that reads back
+    /// to the same `f64`, so e.g. `1000 * 0.00125 - 1.0` is written as `0.25`.
+    fn push_number(&mut self, value: Value, options: &DecodeOptions) {
+        let scaled = options.scale.is_some() || options.offset.is_some();
+        let as_f64 = value.as_f64().unwrap_or(f64::NAN) * options.scale.unwrap_or(1.0) + options.offset.unwrap_or(0.0);
+        let min = options.filter_min.unwrap_or(f64::NEG_INFINITY);
+        let max = options.filter_max.unwrap_or(f64::INFINITY);
+        let filtering = options.filter_min.is_some() || options.filter_max.is_some();
+        // NaN is never in range
+        if filtering && !(min..=max).contains(&as_f64) {
+            self.filtered = true;
+            self.values.push(Value::Filtered);
+        } else if scaled {
+            self.values.push(Value::F64(as_f64));
+        } else {
+            self.values.push(value);
+        }
+    }
+}
+
+fn decode_values(bytes: &[u8], schema: &[DataType], options: &DecodeOptions) -> Row {
+    let mut cursor = Cursor::new(bytes);
+    match options.endianness {
+        Endianness::Big | Endianness::Auto => decode::<BigEndian>(&mut cursor, schema, options),
+        Endianness::Little => decode::<LittleEndian>(&mut cursor, schema, options),
+    }
+}
+
+/// Guesses the byte order of `packets` by decoding them both ways and picking the one whose
+/// values look more plausible, that is smaller in magnitude and without NaN, infinities or
+/// vanishingly small floats. This is a best effort, a tie goes to big endian.
+pub fn detect_endianness(packets: &[&[u8]], schema: &[DataType], options: &DecodeOptions) -> Endianness {
+    let big = implausibility(packets, schema, &DecodeOptions { endianness: Endianness::Big, ..raw(options) });
+    let little = implausibility(packets, schema, &DecodeOptions { endianness: Endianness::Little, ..raw(options) });
+    if little < big { Endianness::Little } else { Endianness::Big }
+}
+
+/// `options` without anything that changes the decoded values after reading them.
+fn raw(options: &DecodeOptions) -> DecodeOptions {
+    DecodeOptions { fixed_point: None, scale: None, offset: None, filter_min: None, filter_max: None, ..*options }
+}
+
+/// Sum of the orders of magnitude of all numeric values, with a penalty for values that
+/// hardly ever come from a real sensor. Types that read the same either way add equally to both.
+fn implausibility(packets: &[&[u8]], schema: &[DataType], options: &DecodeOptions) -> f64 {
+    packets.iter()
+        .flat_map(|packet| decode_values(packet, schema, options).values)
+        .filter_map(|value| value.as_f64())
+        .map(|value| match value.abs() {
+            magnitude if !magnitude.is_finite() || magnitude > 1e15 => 100.0,
+            magnitude if magnitude != 0.0 && magnitude < 1e-15 => 100.0,
+            magnitude => (1.0 + magnitude).ln(),
+        })
+        .sum()
+}
+
+/// Number of bytes at the end of a packet of `len` bytes that are too short for the next
+/// value of `schema`, they are dropped by [`decode_row`] and an error for [`decode_packet`].
+pub fn trailing_bytes(len: usize, schema: &[DataType]) -> usize {
+    // the schema isn't repeated once a value took the rest of the packet
+    let takes_rest = schema.iter().any(DataType::takes_rest);
+    let record_width: usize = schema.iter().map(DataType::width).sum();
+    if record_width == 0 && !takes_rest {
+        return len;
+    }
+    let mut rest = if takes_rest { len } else { len % record_width };
+    for data_type in schema {
+        if data_type.takes_rest() {
+            return 0;
+        }
+        if rest < data_type.width() {
+            break;
+        }
+        rest -= data_type.width();
+    }
+    rest
+}
+
+/// Number of values a packet of `len` bytes decodes to, before filtering, padding and `max_values`.
+pub fn value_count(len: usize, schema: &[DataType], options: &DecodeOptions) -> usize {
+    let takes_rest = schema.iter().any(DataType::takes_rest);
+    let record_width: usize = schema.iter().map(DataType::width).sum();
+    if record_width == 0 && !takes_rest {
+        return 0;
+    }
+    let (mut count, mut rest) = if takes_rest {
+        (0, len)
+    } else {
+        let record_values: usize = schema.iter().map(|&data_type| options.values_per_field(data_type)).sum();
+        (len / record_width * record_values, len % record_width)
+    };
+    for &data_type in schema {
+        if rest == 0 {
+            break;
+        }
+        if data_type.takes_rest() {
+            return count + 1;
+        }
+        if rest < data_type.width() {
+            break;
+        }
+        rest -= data_type.width();
+        count += options.values_per_field(data_type);
+    }
+    count
+}
+
+fn decode<E: ByteOrder>(cursor: &mut Cursor<&[u8]>, schema: &[DataType], options: &DecodeOptions) -> Row {
+    let mut row = Row::default();
+    if schema.is_empty() {
+        return row;
+    }
+    'read: loop {
+        for (i, &data_type) in schema.iter().enumerate() {
+            if options.max_values.is_some_and(|max| row.values.len() >= max) {
+                break 'read;
+            }
+            let left = cursor.get_ref().len() - cursor.position() as usize;
+            if left == 0 {
+                // a packet may end after a whole record without padding
+                if i > 0 {
+                    row.pad(&schema[i..], options);
+                }
+                break 'read;
+            }
+            if left < data_type.width() {
+                row.error = Some(DecodeError::Truncated { expected: data_type.width(), got: left });
+                row.pad(&schema[i..], options);
+                break 'read;
+            }
+            if let Err(e) = read_value::<E>(cursor, data_type, options, &mut row) {
+                row.error = Some(e.into());
+                row.pad(&schema[i..], options);
+                break 'read;
+            }
+        }
+    }
+    // a bool adds several values at once
+    if let Some(max) = options.max_values {
+        row.values.truncate(max);
+    }
+    row
+}
+
+fn read_value<E: ByteOrder>(cursor: &mut Cursor<&[u8]>, data_type: DataType, options: &DecodeOptions, row: &mut Row) -> std::io::Result<()> {
+    match data_type {
+        DataType::Bool => {
+            let value = cursor.read_u8()?;
+            let bits = options.bool_bits.clamp(1, 8);
+            for i in 0..bits {
+                let bit = match options.bool_order {
+                    BitOrder::Lsb => i,
+                    BitOrder::Msb => bits - 1 - i,
+                };
+                row.values.push(Value::Bool(value >> bit & 1 != 0));
+            }
+        },
+        DataType::U8 =>  row.push_integer(Value::U8(cursor.read_u8()?), options),
+        DataType::U16 => row.push_integer(Value::U16(cursor.read_u16::<E>()?), options),
+        DataType::U24 => row.push_integer(Value::U32(cursor.read_u24::<E>()?), options),
+        DataType::U32 => row.push_integer(Value::U32(cursor.read_u32::<E>()?), options),
+        DataType::U64 => row.push_integer(Value::U64(cursor.read_u64::<E>()?), options),
+        DataType::I8 =>  row.push_integer(Value::I8(cursor.read_i8()?), options),
+        DataType::I16 => row.push_integer(Value::I16(cursor.read_i16::<E>()?), options),
+        DataType::I24 => row.push_integer(Value::I32(cursor.read_i24::<E>()?), options),
+        DataType::I32 => row.push_integer(Value::I32(cursor.read_i32::<E>()?), options),
+        DataType::I64 => row.push_integer(Value::I64(cursor.read_i64::<E>()?), options),
+        DataType::F32 => row.push_number(Value::F32(cursor.read_f32::<E>()?), options),
+        DataType::F64 => row.push_number(Value::F64(cursor.read_f64::<E>()?), options),
+        DataType::Hex => {
+            let rest = &cursor.get_ref()[cursor.position() as usize..];
+            let separator = if options.hex_spaced { " " } else { "" };
+            let bytes: Vec<String> = rest.iter().map(|byte| format!("{byte:02x}")).collect();
+            row.values.push(Value::Hex(bytes.join(separator)));
+            cursor.set_position(cursor.get_ref().len() as u64);
+        },
+        DataType::Ascii => {
+            let rest = &cursor.get_ref()[cursor.position() as usize..];
+            row.values.push(Value::Text(String::from_utf8_lossy(rest).into_owned()));
+            cursor.set_position(cursor.get_ref().len() as u64);
+        },
+    };
+    Ok(())
+}
@@ -1,72 +1,769 @@
-use std::path::PathBuf; 
-use std::net::UdpSocket;
-use std::time::Duration;
-use clap::{Parser, ValueEnum};
+#[cfg(feature = "async")]
+mod async_capture;
+#[cfg(feature = "msgpack")]
+mod msgpack_output;
+mod output;
+#[cfg(feature = "parquet")]
+mod parquet_output;
+#[cfg(feature = "sqlite")]
+mod sqlite_output;
+
+use std::path::{Path, PathBuf};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, TcpListener, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use csv::{QuoteStyle, Terminator};
 use std::fmt;
+use std::fmt::Write as _;
+use std::collections::{BTreeMap, VecDeque};
 
 use local_ip_address::list_afinet_netifas;
+use log::{debug, error, info, warn};
 
 use std::thread;
-use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
 
-use std::io::Cursor;
-use byteorder::{NetworkEndian, ReadBytesExt};
+use udp_to_csv::{decode_row, detect_endianness, trailing_bytes, value_count, BitOrder, Checksum, CsvRows, CsvTokens, DataType, DecodeOptions, Deframer, Endianness, FilterMode, Framing, RecvErrors, Value};
+use output::{output_batch, OutputFile};
 
-use std::fs::OpenOptions;
 use std::io::prelude::*;
+use byteorder::{BigEndian, ReadBytesExt};
+use socket2::{Domain, SockRef, Socket, Type};
 
+/// Every option can also be set through the `UDP_TO_CSV_<OPTION>` environment variable,
+/// e.g. `UDP_TO_CSV_PORT`, arguments given on the command line take precedence.
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Address of local interface
-    #[arg(long, short)]
-    bind: std::net::IpAddr,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Local port
-    #[arg(long, short)]
-    port: u16,
+    /// Address of local interface or a hostname resolving to it, all interfaces if not given
+    #[arg(long, short, env = "UDP_TO_CSV_BIND")]
+    bind: Option<String>,
+
+    /// name of the local interface to bind to, e.g. `eth1`, instead of its address
+    #[arg(long, short, conflicts_with = "bind", env = "UDP_TO_CSV_INTERFACE")]
+    interface: Option<String>,
+
+    /// listen on all IPv6 interfaces (::) instead of all IPv4 interfaces (0.0.0.0) if --bind is not given,
+    /// with --interface bind to its IPv6 address and prefer the IPv6 address of a --bind hostname
+    #[arg(long, env = "UDP_TO_CSV_IPV6")]
+    ipv6: bool,
+
+    /// retry resolving and binding the address after waiting this long instead of exiting when it
+    /// fails, e.g. 1s for an interface that only gets its address after start; the wait doubles
+    /// with every retry up to a minute
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_RETRY_BIND")]
+    retry_bind: Option<Duration>,
+
+    /// give up after this many retries of --retry-bind, retry until bound if not given
+    #[arg(long, value_name = "N", requires = "retry_bind", env = "UDP_TO_CSV_RETRY_BIND_MAX")]
+    retry_bind_max: Option<u32>,
+
+    /// Local port, repeat it or separate ports with commas to capture several into one output
+    #[arg(long, short, value_delimiter = ',', required_unless_present_any = ["list_interfaces", "input", "path"], env = "UDP_TO_CSV_PORT")]
+    port: Vec<u16>,
+
+    /// replay packets recorded in this file instead of listening, each preceded by its length
+    /// as a big-endian u32, e.g. to re-run a capture offline
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["port", "bind", "interface", "multicast_group"], env = "UDP_TO_CSV_INPUT")]
+    input: Option<PathBuf>,
+
+    /// replay --input with the gaps between packets it recorded, every length is then preceded
+    /// by a big-endian u64 timestamp in microseconds
+    #[arg(long, requires = "input", env = "UDP_TO_CSV_REPLAY_REALTIME")]
+    replay_realtime: bool,
+
+    /// replay --input at this many packets per second instead of as fast as possible
+    #[arg(long, value_name = "PACKETS_PER_SECOND", value_parser = parse_rate, requires = "input", conflicts_with = "replay_realtime", env = "UDP_TO_CSV_REPLAY_RATE")]
+    replay_rate: Option<f64>,
+
+    /// only log errors
+    #[arg(long, short, conflicts_with = "verbose", env = "UDP_TO_CSV_QUIET")]
+    quiet: bool,
+
+    /// also log debug messages
+    #[arg(long, short, env = "UDP_TO_CSV_VERBOSE")]
+    verbose: bool,
+
+    /// print the available network interfaces and exit
+    #[arg(long, env = "UDP_TO_CSV_LIST_INTERFACES")]
+    list_interfaces: bool,
+
+    /// toml file with options keyed by their long name, e.g. `port = [5000, 5001]` or
+    /// `data-type = "i16"`; options given on the command line or in the environment take precedence
+    #[arg(long, value_name = "FILE", env = "UDP_TO_CSV_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// transport protocol, tcp accepts a single connection, uds receives datagrams on the unix
+    /// socket at --path
+    #[arg(value_enum, long, default_value_t = Transport::Udp, env = "UDP_TO_CSV_TRANSPORT")]
+    transport: Transport,
+
+    /// path of the unix datagram socket for --transport uds, removed again when the capture ends
+    #[arg(long, value_name = "SOCKETPATH", required_if_eq("transport", "uds"), conflicts_with_all = ["port", "bind", "interface", "multicast_group", "input"], env = "UDP_TO_CSV_PATH")]
+    path: Option<PathBuf>,
 
-    /// data type of values
-    #[arg(value_enum, short, long, default_value_t = DataType::U16)]
+    /// how records are delimited, u16-length and u32-length expect each record to be preceded by
+    /// its length in network byte order, also several in one datagram
+    #[arg(value_enum, long, default_value_t = Framing::None, env = "UDP_TO_CSV_FRAMING")]
+    framing: Framing,
+
+    /// hex pattern that starts every record, e.g. AA55; bytes before the first one are dropped
+    /// and a record ends at the next sync word or the end of its datagram
+    #[arg(long, value_name = "HEX", value_parser = parse_hex, conflicts_with = "framing", env = "UDP_TO_CSV_SYNC_WORD")]
+    // spelled out so clap takes the pattern as one value instead of a list of bytes
+    sync_word: Option<std::vec::Vec<u8>>,
+
+    /// checksum of each record to verify, records that don't match are dropped and counted; it is
+    /// stripped before decoding and read in the byte order of --endianness
+    #[arg(value_enum, long, default_value_t = Checksum::None, env = "UDP_TO_CSV_CHECKSUM")]
+    checksum: Checksum,
+
+    /// where --checksum sits in the record
+    #[arg(value_enum, long, default_value_t = ChecksumPosition::Trailing, env = "UDP_TO_CSV_CHECKSUM_POSITION")]
+    checksum_position: ChecksumPosition,
+
+    /// size of the receive buffer in bytes, longer datagrams are truncated
+    #[arg(long, default_value_t = 512, value_parser = clap::value_parser!(u32).range(1..=65536), env = "UDP_TO_CSV_BUFFER_SIZE")]
+    buffer_size: u32,
+
+    /// multicast group to join, the bind address or --interface selects the interface;
+    /// defaults to the bind address if that is a multicast address.
+    /// IPv6 groups take the interface as zone, e.g. `ff02::1%eth0` or `ff02::1%2`, link-local groups
+    /// (ff02::/16) need one to receive anything; interface names are only resolved on unix,
+    /// elsewhere give the interface index
+    #[arg(long, value_parser = parse_multicast_group, env = "UDP_TO_CSV_MULTICAST_GROUP")]
+    multicast_group: Option<MulticastGroup>,
+
+    /// set SO_REUSEADDR so other captures can bind the same address, e.g. to share a multicast feed
+    #[arg(long, env = "UDP_TO_CSV_REUSE_ADDRESS")]
+    reuse_address: bool,
+
+    /// set SO_REUSEPORT so several captures can bind the same port
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    #[arg(long, env = "UDP_TO_CSV_REUSE_PORT")]
+    reuse_port: bool,
+
+    /// size of the kernel receive buffer (SO_RCVBUF) in bytes, raise it if bursts get dropped
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), env = "UDP_TO_CSV_RCVBUF")]
+    rcvbuf: Option<u32>,
+
+    /// take --timestamp from the time the kernel received a datagram (SO_TIMESTAMPNS) instead
+    /// of when it was read, without the delay of scheduling; linux and udp only
+    #[arg(long, env = "UDP_TO_CSV_KERNEL_TIMESTAMP")]
+    kernel_timestamp: bool,
+
+    /// data type of values, hex writes the whole packet as one hex string and ascii as text
+    #[arg(value_enum, short, long, default_value_t = DataType::U16, global = true, env = "UDP_TO_CSV_DATA_TYPE")]
     data_type: DataType,
 
-    /// csv file to write, if not given print to stdout
-    #[arg(short, long)]
+    /// comma separated data types of a record, e.g. u8,i16,i16,u32, repeated until the packet ends;
+    /// takes precedence over --data-type
+    #[arg(long, value_delimiter = ',', global = true, env = "UDP_TO_CSV_SCHEMA")]
+    schema: Vec<DataType>,
+
+    /// comma separated data types of a header at the start of every packet, e.g. u8,u16, decoded
+    /// once before --schema or --data-type repeat over the rest; its values come first in a row
+    #[arg(long, value_delimiter = ',', env = "UDP_TO_CSV_HEADER_SCHEMA")]
+    header_schema: Vec<DataType>,
+
+    /// byte order of multi-byte values, auto guesses it from the first packets; see --bool-order
+    /// for the order of the bits of a bool
+    #[arg(value_enum, short, long, default_value_t = Endianness::Big, global = true, env = "UDP_TO_CSV_ENDIANNESS")]
+    endianness: Endianness,
+
+    /// output format, jsonl writes one json array per packet or an object if --columns is given,
+    /// parquet writes typed columns to --output and needs the `parquet` feature, msgpack writes
+    /// every row as an array prefixed with its length as a big-endian u32 and needs the `msgpack` feature
+    #[arg(value_enum, long, default_value_t = Format::Csv, env = "UDP_TO_CSV_FORMAT")]
+    format: Format,
+
+    /// how rows are terminated, crlf for tools on windows that expect it
+    #[arg(value_enum, long, default_value_t = LineEnding::Lf, env = "UDP_TO_CSV_LINE_ENDING")]
+    line_ending: LineEnding,
+
+    /// number of low bits of each bool byte to emit as columns
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=8), env = "UDP_TO_CSV_BOOL_BITS")]
+    bool_bits: u8,
+
+    /// whether the least or most significant emitted bit of a bool byte comes first;
+    /// a bool is a single byte, so --endianness doesn't change its bits
+    #[arg(value_enum, long, default_value_t = BitOrder::Lsb, env = "UDP_TO_CSV_BOOL_ORDER")]
+    bool_order: BitOrder,
+
+    /// separate the bytes of hex values with spaces, e.g. `0a 1b 2c`
+    #[arg(long, env = "UDP_TO_CSV_HEX_SPACED")]
+    hex_spaced: bool,
+
+    /// read integer values as fixed-point numbers with this many fractional bits, e.g. 15 for
+    /// Q15 as i16, and write them as floats; --scale and --offset apply to the real value afterwards
+    #[arg(long, value_name = "FRAC_BITS", value_parser = clap::value_parser!(u8).range(1..=63), env = "UDP_TO_CSV_FIXED_POINT")]
+    fixed_point: Option<u8>,
+
+    /// multiply numeric values by this factor, the result is written as a float
+    #[arg(long, env = "UDP_TO_CSV_SCALE")]
+    scale: Option<f64>,
+
+    /// add this to numeric values after scaling, the result is written as a float
+    #[arg(long, allow_negative_numbers = true, env = "UDP_TO_CSV_OFFSET")]
+    offset: Option<f64>,
+
+    /// filter numeric values below this, compared after scaling
+    #[arg(long, allow_negative_numbers = true, env = "UDP_TO_CSV_FILTER_MIN")]
+    filter_min: Option<f64>,
+
+    /// filter numeric values above this, compared after scaling
+    #[arg(long, allow_negative_numbers = true, env = "UDP_TO_CSV_FILTER_MAX")]
+    filter_max: Option<f64>,
+
+    /// whether a filtered value drops the whole row or is written as an empty field
+    #[arg(value_enum, long, default_value_t = FilterMode::DropRow, env = "UDP_TO_CSV_FILTER_MODE")]
+    filter_mode: FilterMode,
+
+    /// written for a blank filtered value and, padding the last record to its full length,
+    /// for the values a short packet is missing, e.g. `NaN`; null in jsonl
+    #[arg(long, value_name = "STRING", env = "UDP_TO_CSV_NULL_VALUE")]
+    null_value: Option<String>,
+
+    /// written in csv for a float that is NaN, e.g. an empty string or null for parsers that
+    /// don't read it; null in jsonl
+    #[arg(long, value_name = "STRING", default_value = "NaN", env = "UDP_TO_CSV_FLOAT_NAN_TOKEN")]
+    float_nan_token: String,
+
+    /// written in csv for a float that is infinite, negative infinity gets a leading minus unless
+    /// --float-neg-inf-token is given; null in jsonl
+    #[arg(long, value_name = "STRING", default_value = "inf", env = "UDP_TO_CSV_FLOAT_INF_TOKEN")]
+    float_inf_token: String,
+
+    /// written in csv for a float that is negative infinity instead of --float-inf-token with a minus
+    #[arg(long, value_name = "STRING", env = "UDP_TO_CSV_FLOAT_NEG_INF_TOKEN")]
+    float_neg_inf_token: Option<String>,
+
+    /// stop decoding a packet after this many values, a safety valve against huge rows
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), env = "UDP_TO_CSV_MAX_VALUES_PER_PACKET")]
+    max_values_per_packet: Option<u32>,
+
+    /// quote every non-numeric field such as the source address, fields containing the delimiter,
+    /// quotes or line breaks are always quoted
+    #[arg(long, env = "UDP_TO_CSV_QUOTE_NON_NUMERIC")]
+    quote_non_numeric: bool,
+
+    /// field delimiter
+    #[arg(long, default_value_t = ',', value_parser = parse_delimiter, env = "UDP_TO_CSV_DELIMITER")]
+    delimiter: char,
+
+    /// timestamp of arrival as first column, monotonic_ns counts nanoseconds since the capture
+    /// started on a clock that isn't adjusted like the wall clock, for intervals between packets
+    #[arg(value_enum, long, default_value_t = Timestamp::None, env = "UDP_TO_CSV_TIMESTAMP")]
+    timestamp: Timestamp,
+
+    /// running packet index starting at 0 as the first column
+    #[arg(long, env = "UDP_TO_CSV_INDEX_COLUMN")]
+    index_column: bool,
+
+    /// sender address as a column after the timestamp
+    #[arg(long, env = "UDP_TO_CSV_SOURCE_COLUMN")]
+    source_column: bool,
+
+    /// local port the packet arrived on as a column, after the source address
+    #[arg(long, env = "UDP_TO_CSV_PORT_COLUMN")]
+    port_column: bool,
+
+    /// number of decoded values as the last column, to spot short packets
+    #[arg(long, env = "UDP_TO_CSV_COUNT_COLUMN")]
+    count_column: bool,
+
+    /// write a header row naming the columns
+    #[arg(long, env = "UDP_TO_CSV_HEADER")]
+    header: bool,
+
+    /// start the output with `#` comment lines recording the source, data type, endianness,
+    /// delimiter and start time of the capture, repeated in every rotated file
+    #[arg(long, env = "UDP_TO_CSV_METADATA")]
+    metadata: bool,
+
+    /// write every column as a row once the capture ends, the header becomes the first column;
+    /// all rows are kept in memory so it needs --max-packets or --duration
+    #[arg(long, env = "UDP_TO_CSV_TRANSPOSE")]
+    transpose: bool,
+
+    /// comma separated column names for the header row
+    #[arg(long, value_delimiter = ',', env = "UDP_TO_CSV_COLUMNS")]
+    columns: Vec<String>,
+
+    /// comma separated 0-based positions of the decoded values to write, in this order, e.g.
+    /// 3,7,12; positions past the end of a packet write --null-value
+    #[arg(long, value_delimiter = ',', env = "UDP_TO_CSV_SELECT_COLUMNS")]
+    select_columns: Vec<usize>,
+
+    /// stop after receiving this many packets, only counting those kept by --sample-every and --sample-hz
+    #[arg(long, env = "UDP_TO_CSV_MAX_PACKETS")]
+    max_packets: Option<usize>,
+
+    /// write the row of the first packet right away and exit, short for --max-packets 1
+    /// --flush-every 1, e.g. to poll a device from a shell loop
+    #[arg(long, conflicts_with_all = ["max_packets", "sample", "flush_every", "flush_interval"], env = "UDP_TO_CSV_ONCE")]
+    once: bool,
+
+    /// only keep every Nth packet
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), env = "UDP_TO_CSV_SAMPLE_EVERY")]
+    sample_every: Option<u64>,
+
+    /// drop packets to keep roughly this many per second
+    #[arg(long, value_parser = parse_rate, env = "UDP_TO_CSV_SAMPLE_HZ")]
+    sample_hz: Option<f64>,
+
+    /// receive this many packets and report how they decode on stderr instead of writing any output
+    #[arg(long, env = "UDP_TO_CSV_SAMPLE")]
+    sample: Option<usize>,
+
+    /// stop after capturing for this long, e.g. 30s, 5m or 1h30m
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_DURATION")]
+    duration: Option<Duration>,
+
+    /// warn each time no data arrived for this long, e.g. 10s
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_IDLE_TIMEOUT")]
+    idle_timeout: Option<Duration>,
+
+    /// log the packets received, rows written, size of the output file and udp datagrams the kernel
+    /// dropped this often, e.g. 1m
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_PROGRESS_INTERVAL")]
+    progress_interval: Option<Duration>,
+
+    /// stop the capture once --idle-timeout passed without data instead of waiting on
+    #[arg(long, requires = "idle_timeout", env = "UDP_TO_CSV_EXIT_ON_IDLE")]
+    exit_on_idle: bool,
+
+    /// stop with an error after this many receive errors in a row, the wait after each of them
+    /// doubles up to a second
+    #[arg(long, value_name = "N", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..), env = "UDP_TO_CSV_MAX_RECV_ERRORS")]
+    max_recv_errors: u32,
+
+    /// write buffered rows to the output file after this many packets
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..), env = "UDP_TO_CSV_FLUSH_EVERY")]
+    flush_every: u64,
+
+    /// also write buffered rows to the output file once this much time has passed, e.g. 5s
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_FLUSH_INTERVAL")]
+    flush_interval: Option<Duration>,
+
+    /// csv file to write, if not given print to stdout; a FIFO is written as one stream and
+    /// rows are dropped with a warning while no reader has it open
+    #[arg(short, long, global = true, env = "UDP_TO_CSV_OUTPUT")]
     output: Option<PathBuf>,
+
+    /// start a new row after this many values instead of one per datagram or read, so a tcp
+    /// stream gets rows of the same length; what is left at the end of a datagram or stream is a
+    /// shorter last row. The file given to convert has one record of --schema per row without it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), global = true, env = "UDP_TO_CSV_VALUES_PER_ROW")]
+    values_per_row: Option<u32>,
+
+    /// also print every row to stdout as it arrives, --output still gets them in batches
+    #[arg(long, requires = "output", conflicts_with = "transpose", env = "UDP_TO_CSV_TEE")]
+    tee: bool,
+
+    /// sqlite database to insert the rows into instead of writing csv, committed on every flush;
+    /// needs the `sqlite` feature
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "format", "transpose"], env = "UDP_TO_CSV_OUTPUT_SQLITE")]
+    output_sqlite: Option<PathBuf>,
+
+    /// table of --output-sqlite, created with a column per value if it doesn't exist
+    #[arg(long, default_value = "packets", requires = "output_sqlite", env = "UDP_TO_CSV_TABLE")]
+    table: String,
+
+    /// append to an existing output file instead of replacing it, a file that isn't empty keeps
+    /// its header and --metadata instead of getting them again
+    #[arg(long, overrides_with = "truncate", env = "UDP_TO_CSV_APPEND")]
+    append: bool,
+
+    /// replace an existing output file, the default
+    #[arg(long, overrides_with = "append", env = "UDP_TO_CSV_TRUNCATE")]
+    truncate: bool,
+
+    /// only write packets around one with a value above this, like the trigger of an oscilloscope
+    #[arg(long, allow_negative_numbers = true, env = "UDP_TO_CSV_TRIGGER_THRESHOLD")]
+    trigger_threshold: Option<f64>,
+
+    /// packets kept in memory and written before the one that triggers
+    #[arg(long, value_name = "N", default_value_t = 100, requires = "trigger_threshold", env = "UDP_TO_CSV_PRETRIGGER")]
+    pretrigger: usize,
+
+    /// packets written after the one that triggers
+    #[arg(long, value_name = "M", default_value_t = 100, requires = "trigger_threshold", env = "UDP_TO_CSV_POSTTRIGGER")]
+    posttrigger: usize,
+
+    /// decode packets on this many threads, rows are still written in order of arrival
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..), env = "UDP_TO_CSV_WORKERS")]
+    workers: u8,
+
+    /// packets that can wait for the writer, further udp packets are dropped until it catches up
+    /// and tcp or --input reads wait
+    #[arg(long, default_value_t = 10000, value_parser = clap::value_parser!(u32).range(1..), env = "UDP_TO_CSV_CHANNEL_CAPACITY")]
+    channel_capacity: u32,
+
+    /// receive on a tokio runtime instead of a blocking socket
+    #[cfg(feature = "async")]
+    #[arg(long = "async", env = "UDP_TO_CSV_ASYNC")]
+    use_async: bool,
+
+    /// sync the output file to disk after every flush so a power loss can't take written rows
+    /// with it, slows down writing considerably
+    #[arg(long, env = "UDP_TO_CSV_FSYNC")]
+    fsync: bool,
+
+    /// continue in a new numbered file, e.g. out.1.csv, once the output file grows past this many bytes
+    #[arg(long, env = "UDP_TO_CSV_MAX_FILE_SIZE")]
+    max_file_size: Option<u64>,
+
+    /// start a new output file named after the start time every interval, e.g. 1h gives out-2024-06-01T13.csv
+    #[arg(long, value_parser = parse_duration, env = "UDP_TO_CSV_ROTATE_INTERVAL")]
+    rotate_interval: Option<Duration>,
+
+    /// compress the output file, gzip expects an output path ending in .gz
+    #[arg(value_enum, long, default_value_t = Compression::None, requires_if("gzip", "output"), env = "UDP_TO_CSV_COMPRESS")]
+    compress: Compression,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// decode a binary file of concatenated values instead of capturing, e.g. the dump of a
+    /// data logger; options other than --data-type, --schema, --endianness, --output and
+    /// --values-per-row go before `convert`
+    Convert {
+        /// file to decode
+        #[arg(long = "in", value_name = "FILE")]
+        input: PathBuf,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+enum Transport {
+    Udp,
+    Tcp,
+    Uds,
+}
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Uds => "uds",
+        })
+    }
+}
+impl std::str::FromStr for Transport {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "UDP" => Ok(Transport::Udp),
+            "TCP" => Ok(Transport::Tcp),
+            "UDS" => Ok(Transport::Uds),
+            "UNIX" => Ok(Transport::Uds),
+            _ => Err("invalid transport"),
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum Format {
+    Csv,
+    Jsonl,
+    Parquet,
+    Msgpack,
+}
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Format::Csv =>   "csv",
+            Format::Jsonl => "jsonl",
+            Format::Parquet => "parquet",
+            Format::Msgpack => "msgpack",
+        })
+    }
+}
+impl std::str::FromStr for Format {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "CSV" =>   Ok(Format::Csv),
+            "JSONL" => Ok(Format::Jsonl),
+            "NDJSON" => Ok(Format::Jsonl),
+            "PARQUET" => Ok(Format::Parquet),
+            "MSGPACK" => Ok(Format::Msgpack),
+            _ => Err("invalid output format"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LineEnding {
+    Lf,
+    Crlf,
+}
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            LineEnding::Lf =>   "lf",
+            LineEnding::Crlf => "crlf",
+        })
+    }
+}
+impl std::str::FromStr for LineEnding {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LF" =>   Ok(LineEnding::Lf),
+            "CRLF" => Ok(LineEnding::Crlf),
+            _ => Err("invalid line ending"),
+        }
+    }
+}
+impl LineEnding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ChecksumPosition {
+    /// the last bytes of the record
+    Trailing,
+}
+impl fmt::Display for ChecksumPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            ChecksumPosition::Trailing => "trailing",
+        })
+    }
+}
+impl std::str::FromStr for ChecksumPosition {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "TRAILING" => Ok(ChecksumPosition::Trailing),
+            _ => Err("invalid checksum position"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compression {
+    None,
+    Gzip,
+}
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+        })
+    }
+}
+impl std::str::FromStr for Compression {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "NONE" => Ok(Compression::None),
+            "GZIP" => Ok(Compression::Gzip),
+            "GZ" =>   Ok(Compression::Gzip),
+            _ => Err("invalid compression"),
+        }
+    }
+}
+
+enum Source {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Uds(std::os::unix::net::UnixDatagram),
+    /// recorded packets given by --input
+    Replay(std::fs::File, Pacing),
+}
+
+/// How fast recorded packets are replayed.
+#[derive(Clone, Copy)]
+enum Pacing {
+    /// as fast as the writer takes them
+    None,
+    /// one packet per interval, --replay-rate
+    Rate(Duration),
+    /// the gaps between the timestamps in front of each packet, --replay-realtime
+    Recorded,
 }
 
 #[derive(Clone, ValueEnum)]
-enum DataType {
-    Bool,
-    U8,
-    U16,
-    I8,
-    I16,
-}
-impl fmt::Display for DataType {
+enum Timestamp {
+    None,
+    #[value(name = "unix_ms")]
+    UnixMs,
+    Rfc3339,
+    #[value(name = "monotonic_ns")]
+    MonotonicNs,
+}
+impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
-            DataType::Bool => "bool",
-            DataType::U8 =>  "u8",
-            DataType::U16 => "u16",
-            DataType::I8 =>  "i8",
-            DataType::I16 => "i16",
+            Timestamp::None =>        "none",
+            Timestamp::UnixMs =>      "unix_ms",
+            Timestamp::Rfc3339 =>     "rfc3339",
+            Timestamp::MonotonicNs => "monotonic_ns",
         })
     }
 }
-impl std::str::FromStr for DataType {
+impl std::str::FromStr for Timestamp {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_uppercase().as_str() {
-            "BOOLEAN" => Ok(DataType::Bool),
-            "BOOL" =>    Ok(DataType::Bool),
-            "U8" =>  Ok(DataType::U8),
-            "U16" => Ok(DataType::U16),
-            "I8" =>  Ok(DataType::I8),
-            "I16" => Ok(DataType::I16),
-            _ => Err("invalid datatype"),
+            "NONE" =>         Ok(Timestamp::None),
+            "UNIX_MS" =>      Ok(Timestamp::UnixMs),
+            "RFC3339" =>      Ok(Timestamp::Rfc3339),
+            "MONOTONIC_NS" => Ok(Timestamp::MonotonicNs),
+            _ => Err("invalid timestamp format"),
+        }
+    }
+}
+
+/// Multicast group with the zone of an IPv6 group, an interface name or index.
+#[derive(Clone)]
+struct MulticastGroup {
+    address: IpAddr,
+    zone: Option<String>,
+}
+
+fn parse_multicast_group(s: &str) -> Result<MulticastGroup, String> {
+    let (address, zone) = match s.split_once('%') {
+        Some((address, zone)) => (address, Some(zone.to_owned())),
+        None => (s, None),
+    };
+    let address: IpAddr = address.parse().map_err(|e| format!("invalid multicast group `{s}`: {e}"))?;
+    if !address.is_multicast() {
+        return Err(format!("{address} is not a multicast address"));
+    }
+    match zone {
+        Some(zone) if zone.is_empty() => Err(format!("empty zone in `{s}`")),
+        Some(_) if address.is_ipv4() => Err(format!("`{s}`: only IPv6 groups take a zone")),
+        zone => Ok(MulticastGroup { address, zone }),
+    }
+}
+
+/// Resolves an interface name or index to the index multicast joins expect.
+fn interface_index(interface: &str) -> std::io::Result<u32> {
+    if let Ok(index) = interface.parse() {
+        return Ok(index);
+    }
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(interface).map_err(std::io::Error::other)?;
+        // SAFETY: `name` is a valid nul terminated string that outlives the call
+        match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+            0 => Err(std::io::Error::last_os_error()),
+            index => Ok(index),
+        }
+    }
+    #[cfg(not(unix))]
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "interface names are only resolved on unix, give the interface index"))
+}
+
+struct Packet {
+    time: SystemTime,
+    /// the same on the monotonic clock
+    instant: Instant,
+    source: SocketAddr,
+    /// local port the packet arrived on
+    port: u16,
+    data: Vec<u8>,
+}
+
+/// Parses the command line, options it doesn't give are taken from the `--config` file if there is one.
+fn parse_cli() -> Cli {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    // a first pass only to find the file, the command line may be incomplete without it
+    let matches = Cli::command().ignore_errors(true).get_matches_from(&args);
+    let Some(config) = matches.get_one::<PathBuf>("config") else {
+        return Cli::parse_from(args);
+    };
+    let config_args = match config_args(config, &matches) {
+        Ok(config_args) => config_args,
+        Err(e) => Cli::command().error(clap::error::ErrorKind::InvalidValue, format!("config file {}: {e}", config.display())).exit(),
+    };
+    Cli::parse_from(args.iter().take(1).cloned().chain(config_args).chain(args.iter().skip(1).cloned()))
+}
+
+/// Turns the keys of a toml config file into command line options, leaving out those that
+/// `matches` already got from the command line or the environment.
+fn config_args(config: &Path, matches: &clap::ArgMatches) -> Result<Vec<std::ffi::OsString>, String> {
+    let table: toml::Table = std::fs::read_to_string(config).map_err(|e| e.to_string())?
+        .parse()
+        .map_err(|e: toml::de::Error| e.message().to_owned())?;
+    let command = Cli::command();
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        if id == "config" || !command.get_arguments().any(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some()) {
+            return Err(format!("unknown option `{key}`"));
+        }
+        if matches.value_source(&id).is_some_and(|source| matches!(source, ValueSource::CommandLine | ValueSource::EnvVariable)) {
+            continue;
+        }
+        let option = format!("--{}", id.replace('_', "-"));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => args.push(option.clone().into()),
+                toml::Value::Boolean(false) => {},
+                toml::Value::String(value) => args.push(format!("{option}={value}").into()),
+                toml::Value::Integer(_) | toml::Value::Float(_) => args.push(format!("{option}={value}").into()),
+                _ => return Err(format!("`{key}` has to be a string, number, boolean or an array of them")),
+            }
         }
     }
+    Ok(args)
+}
+
+/// Logs the available interfaces after an address couldn't be used.
+fn log_local_interfaces() {
+    match list_afinet_netifas() {
+        Ok(network_interfaces) => {
+            info!("Available network interfaces:");
+            for (name, ip) in network_interfaces.iter() {
+                info!("\t{}:\t{:?}", name, ip);
+            }
+        },
+        Err(e) => warn!("Error getting network interfaces: {e}"),
+    }
+}
+
+/// Resolves `--bind`, an address is taken as is and of the addresses of a hostname the first
+/// IPv4 one is used, or the first IPv6 one with `ipv6`.
+fn resolve_bind(host: &str, ipv6: bool) -> std::io::Result<IpAddr> {
+    if let Ok(ip) = host.parse() {
+        return Ok(ip);
+    }
+    let addresses: Vec<IpAddr> = (host, 0).to_socket_addrs()?.map(|address| address.ip()).collect();
+    let Some(&address) = addresses.iter().find(|address| address.is_ipv6() == ipv6).or(addresses.first()) else {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found"));
+    };
+    if addresses.len() > 1 {
+        info!("{host} resolves to {} addresses, binding to {address}", addresses.len());
+    }
+    Ok(address)
+}
+
+/// Looks up the first IPv4, or IPv6 if `ipv6` is set, address of the interface called `name`.
+fn interface_address(name: &str, ipv6: bool) -> Result<Option<IpAddr>, local_ip_address::Error> {
+    let network_interfaces = list_afinet_netifas()?;
+    Ok(network_interfaces.into_iter().find(|(interface, ip)| interface == name && ip.is_ipv6() == ipv6).map(|(_, ip)| ip))
 }
 
 fn print_local_interfaces() {
@@ -77,189 +774,2113 @@ fn print_local_interfaces() {
             println!("\t{}:\t{:?}", name, ip);
         }
     } else {
-        println!("Error getting network interfaces: {:?}", network_interfaces);
+        error!("Error getting network interfaces: {:?}", network_interfaces);
     }
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = parse_cli();
+    if cli.once {
+        cli.max_packets = Some(1);
+        cli.flush_every = 1;
+    }
+    if cli.float_neg_inf_token.is_none() {
+        cli.float_neg_inf_token = Some(format!("-{}", cli.float_inf_token));
+    }
 
-    let socket = UdpSocket::bind((cli.bind, cli.port));
-    if let Err(e) = socket {
-        eprintln!("Could not bind to provided address {}:{}; {}", cli.bind, cli.port, e);
-        println!("Avaliable network interfaces: ");
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => "error",
+        (false, true) => "debug",
+        (false, false) => "info",
+    };
+    // RUST_LOG still takes precedence for finer control
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level))
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    if let Some(config) = &cli.config {
+        debug!("read options from {}", config.display());
+    }
+    if cli.list_interfaces {
         print_local_interfaces();
         return;
     }
-    let socket = socket.unwrap();
-    socket.set_read_timeout(None).expect("set_read_timeout call failed");
+    if cli.port.is_empty() && cli.input.is_none() && cli.path.is_none() && cli.command.is_none() {
+        unreachable!("clap requires --port without --list-interfaces, --input, --path or convert");
+    }
+    let bind = match (&cli.bind, &cli.interface) {
+        (Some(host), _) => match retry_bind(&cli, || resolve_bind(host, cli.ipv6).map_err(|e| format!("Could not resolve {host}; {e}"))) {
+            Ok(ip) => ip,
+            Err(e) => {
+                error!("{e}");
+                std::process::exit(1);
+            },
+        },
+        (None, Some(name)) => match retry_bind(&cli, || match interface_address(name, cli.ipv6) {
+            Ok(Some(ip)) => Ok(ip),
+            Ok(None) => Err(format!("No {} address found for interface {name}", if cli.ipv6 { "IPv6" } else { "IPv4" })),
+            Err(e) => Err(format!("Could not look up interface {name}; {e}")),
+        }) {
+            Ok(ip) => ip,
+            Err(e) => {
+                error!("{e}");
+                log_local_interfaces();
+                std::process::exit(1);
+            },
+        },
+        (None, None) => match cli.ipv6 {
+            false => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            true => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        },
+    };
 
-    let (tx, rx) = mpsc::channel();
+    if cli.transpose {
+        if cli.max_packets.is_none() && cli.duration.is_none() {
+            error!("--transpose keeps every row in memory and needs --max-packets or --duration");
+            std::process::exit(1);
+        }
+        if !matches!(cli.format, Format::Csv) {
+            error!("--transpose is only supported with csv format");
+            std::process::exit(1);
+        }
+    }
 
-    thread::spawn(move || {
-        writer(rx, cli);
-    });
+    if let Format::Parquet = cli.format {
+        if cli.tee {
+            error!("--tee prints csv or jsonl rows, it can't be combined with --format parquet");
+            std::process::exit(1);
+        }
+        if cfg!(not(feature = "parquet")) {
+            error!("--format parquet needs udp_to_csv built with the parquet feature");
+            std::process::exit(1);
+        }
+        if cli.output.is_none() {
+            error!("--format parquet needs an --output file");
+            std::process::exit(1);
+        }
+        if cli.append || cli.rotate_interval.is_some() || cli.max_file_size.is_some() || !matches!(cli.compress, Compression::None) {
+            error!("--format parquet writes a single new file, --append, rotation and --compress are not supported");
+            std::process::exit(1);
+        }
+    }
 
-    let mut buffer = [0u8; 512];
-    loop {
-        let recv_result = socket.recv(&mut buffer);
-        match recv_result {
-            Err(e) => { eprintln!("Error receiving message: {e}"); },
-            Ok(len) => { tx.send(buffer[0..len].to_vec()).expect("writer thread disconnected"); },
-        };
+    if let Format::Msgpack = cli.format {
+        if cli.tee {
+            error!("--tee prints csv or jsonl rows, it can't be combined with --format msgpack");
+            std::process::exit(1);
+        }
+        if cfg!(not(feature = "msgpack")) {
+            error!("--format msgpack needs udp_to_csv built with the msgpack feature");
+            std::process::exit(1);
+        }
     }
-}
 
-fn writer(rx: Receiver<Vec<u8>>, options: Cli) {
-    let mut csv_string = "".to_owned();
-    let mut count = 0;
-    loop {
-        let recv_result = rx.try_recv();
-        match recv_result {
-            Err(mpsc::TryRecvError::Empty) => thread::sleep(Duration::from_millis(50)),
-            Err(mpsc::TryRecvError::Disconnected) => {
-                match &options.output {
-                    None => print!("{csv_string}"),
-                    Some(output) => output_csv(&csv_string, output),
-                }
-                eprintln!("recv thread disconnected");
-                return;
-            },
-            Ok(message) => {
-                let mut cursor = Cursor::new(message);
-                match options.data_type {
-                    DataType::Bool => {
-                        'read: loop {
-                            match cursor.read_u8() {
-                                Ok(value) => {
-                                    for i in 0..8 {
-                                        let value_bit = value >> i & 1;
-                                        csv_string.push_str(&value_bit.to_string());
-                                        csv_string.push(',');
-                                    }
-                                },
-                                Err(e) => {
-                                    match e.kind() {
-                                        std::io::ErrorKind::UnexpectedEof => break 'read,
-                                        _ => {
-                                            eprintln!("error while parsing: {e}");
-                                            break 'read;
-                                        }
-                                    };
-                                },
-                            };
-                        }
-                    },
-                    DataType::U8 => { 
-                        'read: loop {
-                            match cursor.read_u8() {
-                                Ok(value) => {
-                                    csv_string.push_str(&value.to_string());
-                                    csv_string.push(',');
-                                },
-                                Err(e) => {
-                                    match e.kind() {
-                                        std::io::ErrorKind::UnexpectedEof => break 'read,
-                                        _ => {
-                                            eprintln!("error while parsing: {e}");
-                                            break 'read;
-                                        }
-                                    };
-                                },
-                            };
-                        }
-                    },
-                    DataType::U16 => { 
-                        'read: loop {
-                            match cursor.read_u16::<NetworkEndian>() {
-                                Ok(value) => {
-                                    csv_string.push_str(&value.to_string());
-                                    csv_string.push(',');
-                                },
-                                Err(e) => {
-                                    match e.kind() {
-                                        std::io::ErrorKind::UnexpectedEof => break 'read,
-                                        _ => {
-                                            eprintln!("error while parsing: {e}");
-                                            break 'read;
-                                        }
-                                    };
-                                },
-                            };
-                        }
-                    },
-                    DataType::I8 => { 
-                        'read: loop {
-                            match cursor.read_i8() {
-                                Ok(value) => {
-                                    csv_string.push_str(&value.to_string());
-                                    csv_string.push(',');
-                                },
-                                Err(e) => {
-                                    match e.kind() {
-                                        std::io::ErrorKind::UnexpectedEof => break 'read,
-                                        _ => {
-                                            eprintln!("error while parsing: {e}");
-                                            break 'read;
-                                        }
-                                    };
-                                },
-                            };
-                        }
-                    },
-                    DataType::I16 => { 
-                        'read: loop {
-                            match cursor.read_i16::<NetworkEndian>() {
-                                Ok(value) => {
-                                    csv_string.push_str(&value.to_string());
-                                    csv_string.push(',');
-                                },
-                                Err(e) => {
-                                    match e.kind() {
-                                        std::io::ErrorKind::UnexpectedEof => break 'read,
-                                        _ => {
-                                            eprintln!("error while parsing: {e}");
-                                            break 'read;
-                                        }
-                                    };
-                                },
-                            };
-                        }
-                    },
-                };
+    if cli.output_sqlite.is_some() {
+        if cfg!(not(feature = "sqlite")) {
+            error!("--output-sqlite needs udp_to_csv built with the sqlite feature");
+            std::process::exit(1);
+        }
+        if cli.append || cli.rotate_interval.is_some() || cli.max_file_size.is_some() || !matches!(cli.compress, Compression::None) {
+            error!("--output-sqlite inserts into a table, --append, rotation and --compress are not supported");
+            std::process::exit(1);
+        }
+    }
 
-                // csv_string.replace_range((csv_string.len()-1)..csv_string.len(), "\n");
-                let _ = csv_string.pop();
-                csv_string.push('\n');
-
-                match &options.output {
-                    None => {
-                        print!("{csv_string}");
-                        csv_string.clear();
-                    },
-                    Some(output) => {
-                        count += 1;
-                        if count >= 1000 {
-                            output_csv(&csv_string, output);
-                            csv_string.clear();
-                            count = 0;
-                        }
-                    },
-                }
-            },
-        };
+    if cli.metadata && (!matches!(cli.format, Format::Csv) || cli.output_sqlite.is_some()) {
+        error!("--metadata writes # comment lines and is only supported with csv output");
+        std::process::exit(1);
+    }
+
+    if cli.header_schema.iter().any(DataType::takes_rest) {
+        error!("--header-schema has a fixed width, hex and ascii take the rest of the packet and belong in --schema");
+        std::process::exit(1);
+    }
+
+    if !matches!(cli.checksum, Checksum::None) && matches!(cli.endianness, Endianness::Auto) {
+        error!("--checksum is read in the byte order of --endianness, set it to big or little");
+        std::process::exit(1);
+    }
+
+    if let Compression::Gzip = cli.compress {
+        let output = cli.output.as_ref().expect("clap requires --output with --compress gzip");
+        if output.extension().is_none_or(|extension| extension != "gz") {
+            warn!("writing gzip compressed output to {} which does not end in .gz", output.display());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    if cli.use_async && !matches!(cli.transport, Transport::Udp) {
+        error!("--async is only supported with udp transport");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "async")]
+    if cli.use_async && cli.port.len() > 1 {
+        error!("--async captures a single port");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "async")]
+    if cli.use_async && cli.input.is_some() {
+        error!("--async receives from a socket, it can't replay --input");
+        std::process::exit(1);
+    }
+    if cli.workers > 1 && matches!(cli.endianness, Endianness::Auto) {
+        error!("--endianness auto needs to see the first packets in one place, use a single worker");
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Convert { input }) = &cli.command {
+        let input = input.clone();
+        if let Err(e) = convert(&input, cli) {
+            error!("Could not convert {}; {e}", input.display());
+            std::process::exit(1);
+        }
+        return;
+    }
+    let (layout, decode_options) = decode_settings(&cli);
+    if cli.kernel_timestamp && (!cfg!(target_os = "linux") || !matches!(cli.transport, Transport::Udp) || cli.input.is_some()) {
+        error!("--kernel-timestamp is only supported for udp on linux");
+        std::process::exit(1);
+    }
+    if cli.values_per_row.is_some() {
+        if !matches!(cli.framing, Framing::None) || cli.sync_word.is_some() || !cli.header_schema.is_empty() {
+            error!("--values-per-row cuts unframed data into rows, it can't be combined with --framing, --sync-word or --header-schema");
+            std::process::exit(1);
+        }
+        if layout.schema.iter().any(DataType::takes_rest) {
+            error!("--values-per-row cuts rows of a fixed width, hex and ascii take the rest of the packet");
+            std::process::exit(1);
+        }
     }
-}
 
-fn output_csv(csv_string: &str, output: &PathBuf) {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let multicast_group = cli.multicast_group.as_ref().map(|group| group.address).or(Some(bind).filter(IpAddr::is_multicast));
+    if multicast_group.is_some() && !matches!(cli.transport, Transport::Udp) {
+        error!("Multicast is only supported with udp transport");
+        std::process::exit(1);
+    }
+    // IPv6 groups are joined on the interface of the zone, or of --interface
+    let zone = cli.multicast_group.as_ref().and_then(|group| group.zone.as_deref()).or(cli.interface.as_deref());
+    let scope_id = match zone.filter(|_| multicast_group.is_some_and(|group| group.is_ipv6())) {
+        None => 0,
+        Some(zone) => match interface_index(zone) {
+            Ok(index) => index,
+            Err(e) => {
+                error!("Could not find interface {zone} for the multicast group; {e}");
+                log_local_interfaces();
+                std::process::exit(1);
+            },
+        },
+    };
+    // a socket has to be bound to the wildcard address to receive multicast traffic
+    let bind_address = match multicast_group {
+        Some(IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        Some(IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        None => bind,
+    };
 
-    if let Err(e) = write!(file, "{csv_string}") {
-        eprintln!("Couldn't write to file: {}", e);
+    let mut sources = Vec::new();
+    for &port in &cli.port {
+        let bound = retry_bind(&cli, || bind_source(SocketAddr::new(bind_address, port), &cli)
+            .map_err(|e| format!("Could not bind to provided address {bind_address}:{port}; {e}")));
+        match bound {
+            Ok(source) => sources.push(source),
+            Err(e) => {
+                error!("{e}");
+                log_local_interfaces();
+                std::process::exit(1);
+            },
+        }
     }
+    // removed on every return from here on
+    let mut _socket_file: Option<SocketFile> = None;
+    if let Some(path) = &cli.path {
+        if !matches!(cli.transport, Transport::Uds) {
+            error!("--path is the unix socket of --transport uds");
+            std::process::exit(1);
+        }
+        #[cfg(unix)]
+        match std::os::unix::net::UnixDatagram::bind(path) {
+            Ok(socket) => {
+                _socket_file = Some(SocketFile(path.clone()));
+                sources.push(Source::Uds(socket));
+            },
+            Err(e) => {
+                error!("Could not bind to {}; {e}", path.display());
+                std::process::exit(1);
+            },
+        }
+        #[cfg(not(unix))]
+        {
+            error!("--transport uds is only supported on unix");
+            std::process::exit(1);
+        }
+    }
+    if let Some(input) = &cli.input {
+        match std::fs::File::open(input) {
+            Ok(file) => {
+                let pacing = match cli.replay_rate {
+                    Some(rate) => Pacing::Rate(Duration::from_secs_f64(1.0 / rate)),
+                    None if cli.replay_realtime => Pacing::Recorded,
+                    None => Pacing::None,
+                };
+                sources.push(Source::Replay(file, pacing));
+            },
+            Err(e) => {
+                error!("Could not open input file {}; {e}", input.display());
+                std::process::exit(1);
+            },
+        }
+    }
+
+    let interface = bind;
+    if let Some(group) = multicast_group {
+        for source in &sources {
+            if let Source::Udp(socket) = source {
+                if let Err(e) = join_multicast(socket, group, interface, scope_id) {
+                    error!("Could not join multicast group {group}; {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // opened only once binding succeeded so a failed start doesn't truncate the previous capture
+    // a sample run only reports to stderr and leaves the output file alone
+    // parquet is written by the writer once the first row fixed the columns
+    let output = cli.output.as_ref().filter(|_| cli.sample.is_none() && !matches!(cli.format, Format::Parquet));
+    let output_file = match output.map(|output| OutputFile::open(output, &cli)).transpose() {
+        Ok(output_file) => output_file,
+        Err(e) => {
+            error!("Could not open output file {}; {e}", cli.output.as_ref().unwrap().display());
+            // exiting skips the drop that removes the unix socket
+            drop(_socket_file);
+            std::process::exit(1);
+        },
+    };
+
+    let limits = Limits {
+        max_packets: cli.sample.or(cli.max_packets),
+        received: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+        deadline: cli.duration.map(|duration| Instant::now() + duration),
+        interrupted: Arc::new(AtomicBool::new(false)),
+        idle_timeout: cli.idle_timeout,
+        exit_on_idle: cli.exit_on_idle,
+        max_recv_errors: cli.max_recv_errors,
+        failed: AtomicBool::new(false),
+        sample_every: cli.sample_every.unwrap_or(1),
+        sample_interval: cli.sample_hz.map(|hz| Duration::from_secs_f64(1.0 / hz)),
+        framing: cli.framing,
+        sync_word: cli.sync_word.clone(),
+        record_len: cli.values_per_row.map(|values| row_len(&layout.schema, &decode_options, values as usize)),
+        checksum: cli.checksum,
+        checksum_position: cli.checksum_position,
+        endianness: cli.endianness,
+        rejected: AtomicUsize::new(0),
+    };
+    // stop receiving on Ctrl-C so the writer still flushes what it has buffered
+    let interrupted = Arc::clone(&limits.interrupted);
+    if let Err(e) = ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst)) {
+        warn!("Could not install Ctrl-C handler; {e}");
+    }
+
+    let kernel_drops = KernelDrops::new(&sources);
+    let buffer_size = cli.buffer_size as usize;
+    match sources.as_slice() {
+        #[cfg(feature = "async")]
+        [Source::Udp(socket)] if cli.use_async => {
+            if let Err(e) = async_capture::capture_udp(socket, output_file, cli, kernel_drops.clone(), &limits, buffer_size) {
+                error!("Could not start async runtime; {e}");
+                limits.fail();
+            }
+        },
+        sources => {
+            // one receiving thread per port, all feeding the same writer
+            let (tx, writer_thread) = spawn_writer(output_file, cli, kernel_drops.clone());
+            thread::scope(|scope| {
+                for source in sources {
+                    let tx = tx.clone();
+                    let limits = &limits;
+                    scope.spawn(move || match source {
+                        Source::Udp(socket) => receive_udp(socket, tx, limits, buffer_size),
+                        Source::Tcp(listener) => receive_tcp(listener, tx, limits, buffer_size),
+                        #[cfg(unix)]
+                        Source::Uds(socket) => receive_uds(socket, tx, limits, buffer_size),
+                        Source::Replay(file, pacing) => replay(file, *pacing, tx, limits),
+                    });
+                }
+            });
+            drop(tx);
+            if let Err(panic) = writer_thread.join() {
+                writer_panicked(panic);
+            }
+        },
+    }
+
+    let dropped = limits.dropped.load(Ordering::SeqCst);
+    if dropped > 0 {
+        warn!("dropped {dropped} packets because the writer fell behind");
+    }
+    let rejected = limits.rejected.load(Ordering::SeqCst);
+    if rejected > 0 {
+        warn!("rejected {rejected} packets with a wrong {} checksum", limits.checksum);
+    }
+    match kernel_drops.count() {
+        Some(0) => debug!("the kernel dropped no datagrams"),
+        Some(drops) => warn!("the kernel dropped {drops} datagrams because the receive buffer was full, raise --rcvbuf"),
+        None => {},
+    }
+
+    if let Some(group) = multicast_group {
+        for source in &sources {
+            if let Source::Udp(socket) = source {
+                if let Err(e) = leave_multicast(socket, group, interface, scope_id) {
+                    warn!("Could not leave multicast group {group}; {e}");
+                }
+            }
+        }
+    }
+
+    if limits.failed.load(Ordering::SeqCst) {
+        // exiting skips the drop that removes the unix socket
+        drop(_socket_file);
+        std::process::exit(1);
+    }
+}
+
+/// Datagrams the kernel dropped on the udp sockets of the capture because their receive buffer was
+/// full, counted in `/proc/net/udp` on linux and unknown elsewhere.
+#[derive(Clone, Default)]
+struct KernelDrops {
+    /// inodes of the sockets, which identify them in `/proc/net/udp`
+    inodes: Vec<u64>,
+}
+impl KernelDrops {
+    fn new(sources: &[Source]) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+            use std::os::unix::fs::MetadataExt;
+            let inodes = sources.iter()
+                .filter_map(|source| match source {
+                    Source::Udp(socket) => std::fs::metadata(format!("/proc/self/fd/{}", socket.as_raw_fd())).ok(),
+                    _ => None,
+                })
+                .map(|metadata| metadata.ino())
+                .collect();
+            Self { inodes }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = sources;
+            Self::default()
+        }
+    }
+
+    /// Drops since the sockets were bound, `None` without udp sockets or if they can't be looked up.
+    fn count(&self) -> Option<u64> {
+        if self.inodes.is_empty() {
+            return None;
+        }
+        let mut found = false;
+        let mut drops = 0;
+        for table in ["/proc/net/udp", "/proc/net/udp6"] {
+            let Ok(table) = std::fs::read_to_string(table) else {
+                continue;
+            };
+            for line in table.lines().skip(1) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                // the inode is the tenth column and the drops the last
+                let (Some(inode), Some(dropped)) = (fields.get(9), fields.last()) else {
+                    continue;
+                };
+                if inode.parse().is_ok_and(|inode: u64| self.inodes.contains(&inode)) {
+                    found = true;
+                    drops += dropped.parse::<u64>().unwrap_or(0);
+                }
+            }
+        }
+        found.then_some(drops)
+    }
+}
+
+/// Decodes the file given to `convert` in rows of --values-per-row values, the last row may be
+/// shorter.
+fn convert(input: &Path, options: Cli) -> std::io::Result<()> {
+    let (layout, decode_options) = decode_settings(&options);
+    if !layout.header.is_empty() {
+        return Err(std::io::Error::other("--header-schema starts every packet, a file has none"));
+    }
+    if layout.schema.iter().any(DataType::takes_rest) {
+        return Err(std::io::Error::other("rows have a fixed width, hex and ascii take the rest of the file"));
+    }
+    let record_values = layout.schema.iter().map(|&data_type| decode_options.values_per_field(data_type)).sum();
+    let row_len = row_len(&layout.schema, &decode_options, options.values_per_row.map_or(record_values, |values| values as usize));
+
+    let mut reader = std::io::BufReader::new(std::fs::File::open(input)?);
+    let output = options.output.as_ref().filter(|_| options.sample.is_none() && !matches!(options.format, Format::Parquet));
+    let output_file = output.map(|output| OutputFile::open(output, &options)).transpose()?;
+    let mut writer = Writer::new(output_file, options, KernelDrops::default());
+    loop {
+        let mut data = Vec::with_capacity(row_len);
+        (&mut reader).take(row_len as u64).read_to_end(&mut data)?;
+        if data.is_empty() {
+            break;
+        }
+        writer.packet(Packet { time: SystemTime::now(), instant: Instant::now(), source: UNADDRESSED, port: 0, data });
+    }
+    writer.finish();
+    Ok(())
+}
+
+/// Bytes of the first `values` values of `schema` repeated, a field isn't split across rows so
+/// a bool may make a row longer.
+fn row_len(schema: &[DataType], options: &DecodeOptions, values: usize) -> usize {
+    let mut len = 0;
+    let mut count = 0;
+    for &data_type in schema.iter().cycle() {
+        if count >= values {
+            break;
+        }
+        len += data_type.width();
+        count += options.values_per_field(data_type);
+    }
+    len
+}
+
+/// Unix socket file of `--transport uds`, removed when the capture is over.
+struct SocketFile(PathBuf);
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Could not remove socket {}; {e}", self.0.display());
+        }
+    }
+}
+
+/// Runs `attempt` until it succeeds, waiting --retry-bind between failures and doubling the wait
+/// up to [`MAX_RETRY_BIND_INTERVAL`]. Returns the last error once --retry-bind-max retries failed.
+fn retry_bind<T>(options: &Cli, mut attempt: impl FnMut() -> Result<T, String>) -> Result<T, String> {
+    let Some(mut interval) = options.retry_bind else {
+        return attempt();
+    };
+    let mut retries = 0;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) if options.retry_bind_max.is_some_and(|max| retries >= max) => return Err(e),
+            Err(e) => {
+                warn!("{e}, retrying in {interval:?}");
+                std::thread::sleep(interval);
+                if interval < MAX_RETRY_BIND_INTERVAL {
+                    interval = (interval * 2).min(MAX_RETRY_BIND_INTERVAL);
+                }
+                retries += 1;
+            },
+        }
+    }
+}
+
+/// Binds the capture socket, the reuse options have to be set before binding to take effect.
+fn bind_source(address: SocketAddr, options: &Cli) -> std::io::Result<Source> {
+    let socket_type = match options.transport {
+        Transport::Udp => Type::DGRAM,
+        Transport::Tcp => Type::STREAM,
+        Transport::Uds => unreachable!("a unix socket is bound to --path"),
+    };
+    let socket = Socket::new(Domain::for_address(address), socket_type, None)?;
+    // like std's TcpListener::bind, so a restarted capture doesn't wait for old connections to time out
+    let tcp_default = cfg!(unix) && matches!(options.transport, Transport::Tcp);
+    if options.reuse_address || tcp_default {
+        socket.set_reuse_address(true)?;
+    }
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    if options.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    if let Some(rcvbuf) = options.rcvbuf {
+        socket.set_recv_buffer_size(rcvbuf as usize)?;
+        // the kernel may cap the size, linux also doubles it for bookkeeping
+        let granted = socket.recv_buffer_size()?;
+        if granted < rcvbuf as usize {
+            warn!("receive buffer is only {granted} bytes instead of {rcvbuf}, the system limit may be lower (net.core.rmem_max on linux)");
+        } else {
+            info!("receive buffer is {granted} bytes");
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if options.kernel_timestamp {
+        use std::os::fd::AsRawFd;
+        let enable: libc::c_int = 1;
+        // SAFETY: the option value is a c_int that lives for the duration of the call
+        let result = unsafe {
+            libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, (&enable as *const libc::c_int).cast(), std::mem::size_of_val(&enable) as libc::socklen_t)
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    socket.bind(&address.into())?;
+    match options.transport {
+        Transport::Tcp => {
+            socket.listen(128)?;
+            Ok(Source::Tcp(socket.into()))
+        },
+        _ => Ok(Source::Udp(socket.into())),
+    }
+}
+
+fn spawn_writer(output_file: Option<OutputFile>, options: Cli, kernel_drops: KernelDrops) -> (SyncSender<Packet>, thread::JoinHandle<()>) {
+    let (tx, rx) = mpsc::sync_channel(options.channel_capacity as usize);
+    let writer_thread = if options.workers > 1 {
+        let decoded = spawn_decoders(rx, &options);
+        thread::spawn(move || writer(decoded, Writer::new(output_file, options, kernel_drops), Writer::decoded))
+    } else {
+        thread::spawn(move || writer(rx, Writer::new(output_file, options, kernel_drops), Writer::packet))
+    };
+    (tx, writer_thread)
+}
+
+/// Starts `--workers` threads that decode packets in parallel, numbered in order of arrival.
+fn spawn_decoders(rx: Receiver<Packet>, options: &Cli) -> Receiver<Decoded> {
+    let (tx, decoded) = mpsc::sync_channel(options.channel_capacity as usize);
+    let (layout, decode_options) = decode_settings(options);
+    // taking a packet and numbering it under one lock keeps the numbers in arrival order
+    let next = Arc::new(Mutex::new((rx, 0u64)));
+    for _ in 0..options.workers {
+        let next = Arc::clone(&next);
+        let tx = tx.clone();
+        let layout = layout.clone();
+        thread::spawn(move || loop {
+            let (sequence, packet) = {
+                let mut next = next.lock().expect("decode worker panicked");
+                let Ok(packet) = next.0.recv() else {
+                    return;
+                };
+                next.1 += 1;
+                (next.1 - 1, packet)
+            };
+            let values = layout.decode_row(&packet.data, &decode_options);
+            if tx.send(Decoded { sequence, packet, values }).is_err() {
+                return;
+            }
+        });
+    }
+    decoded
+}
+
+/// Packet decoded by a worker, `sequence` counts packets in order of arrival.
+struct Decoded {
+    sequence: u64,
+    packet: Packet,
+    values: Option<Vec<Value>>,
+}
+
+/// Returns the local IPv4 interface to join on, any interface if `interface` is not a unicast IPv4 address.
+fn multicast_interface_v4(interface: IpAddr) -> Ipv4Addr {
+    match interface {
+        IpAddr::V4(ip) if !ip.is_multicast() => ip,
+        _ => Ipv4Addr::UNSPECIFIED,
+    }
+}
+
+/// `scope_id` is the interface index for IPv6 groups, 0 lets the system pick the interface.
+fn join_multicast(socket: &UdpSocket, group: IpAddr, interface: IpAddr, scope_id: u32) -> std::io::Result<()> {
+    match group {
+        IpAddr::V4(group) => socket.join_multicast_v4(&group, &multicast_interface_v4(interface)),
+        IpAddr::V6(group) => socket.join_multicast_v6(&group, scope_id),
+    }
+}
+
+fn leave_multicast(socket: &UdpSocket, group: IpAddr, interface: IpAddr, scope_id: u32) -> std::io::Result<()> {
+    match group {
+        IpAddr::V4(group) => socket.leave_multicast_v4(&group, &multicast_interface_v4(interface)),
+        IpAddr::V6(group) => socket.leave_multicast_v6(&group, scope_id),
+    }
+}
+
+/// Length prefixes above this are taken for a stream that is out of step.
+const MAX_RECORD_LEN: usize = 1 << 20;
+
+/// Source of packets without an ip address, replayed or from a unix socket.
+const UNADDRESSED: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+/// How often a blocked receive wakes up to check whether the capture was interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest wait between two attempts of --retry-bind.
+const MAX_RETRY_BIND_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Conditions that end a capture.
+struct Limits {
+    max_packets: Option<usize>,
+    /// packets passed on so far, shared by the receiving threads of all ports
+    received: AtomicUsize,
+    /// packets the writer had no room for
+    dropped: AtomicUsize,
+    deadline: Option<Instant>,
+    /// set by the Ctrl-C handler
+    interrupted: Arc<AtomicBool>,
+    idle_timeout: Option<Duration>,
+    exit_on_idle: bool,
+    max_recv_errors: u32,
+    /// set once a port gave up after --max-recv-errors, it ends the capture of the others too
+    failed: AtomicBool,
+    sample_every: u64,
+    /// shortest time between two kept packets for --sample-hz
+    sample_interval: Option<Duration>,
+    framing: Framing,
+    sync_word: Option<Vec<u8>>,
+    /// bytes of a row of --values-per-row
+    record_len: Option<usize>,
+    checksum: Checksum,
+    checksum_position: ChecksumPosition,
+    /// byte order of the checksum
+    endianness: Endianness,
+    /// packets dropped because their checksum didn't match
+    rejected: AtomicUsize,
+}
+impl Limits {
+    fn reached(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+            || self.failed.load(Ordering::SeqCst)
+            || self.max_packets.is_some_and(|max| self.received.load(Ordering::SeqCst) >= max)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Counts a packet that is about to be passed on, false if --max-packets was already reached
+    /// by another port in the meantime.
+    fn count_packet(&self) -> bool {
+        let received = self.received.fetch_add(1, Ordering::SeqCst);
+        self.max_packets.is_none_or(|max| received < max)
+    }
+
+    /// Counts a packet the writer had no room for, it doesn't count towards --max-packets.
+    fn drop_packet(&self) {
+        self.received.fetch_sub(1, Ordering::SeqCst);
+        if self.dropped.fetch_add(1, Ordering::SeqCst) == 0 {
+            warn!("the writer is falling behind, dropping packets; see --channel-capacity");
+        }
+    }
+
+    /// Strips the checksum off `data`, false if it doesn't match and the packet is rejected.
+    fn verify(&self, data: &mut Vec<u8>, source: SocketAddr) -> bool {
+        let payload = match self.checksum_position {
+            ChecksumPosition::Trailing => self.checksum.verify(data, self.endianness),
+        };
+        let Some(payload) = payload else {
+            debug!("packet from {source} with {} bytes rejected, its {} checksum doesn't match", data.len(), self.checksum);
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+            return false;
+        };
+        let len = payload.len();
+        data.truncate(len);
+        true
+    }
+
+    /// Read timeout that unblocks a receive in time to notice an interrupt or the deadline passing.
+    fn read_timeout(&self) -> Option<Duration> {
+        let timeout = match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).min(POLL_INTERVAL),
+            None => POLL_INTERVAL,
+        };
+        // a zero timeout is rejected by set_read_timeout
+        Some(timeout.max(Duration::from_millis(1)))
+    }
+
+    fn throttle(&self) -> Throttle {
+        Throttle { every: self.sample_every, seen: 0, interval: self.sample_interval, next: Instant::now() }
+    }
+
+    fn deframer(&self) -> Deframer {
+        let deframer = Deframer::new(self.framing, MAX_RECORD_LEN);
+        match (&self.sync_word, self.record_len) {
+            (Some(sync_word), _) => deframer.with_sync_word(sync_word.clone()),
+            (None, Some(record_len)) => deframer.with_record_len(record_len),
+            (None, None) => deframer,
+        }
+    }
+
+    fn idle(&self) -> Idle {
+        Idle { timeout: self.idle_timeout, exit: self.exit_on_idle, since: Instant::now(), warnings: 0 }
+    }
+
+    fn recv_errors(&self) -> RecvErrors {
+        RecvErrors::new(self.max_recv_errors)
+    }
+
+    /// Ends the capture of every port after an error it can't recover from, the process exits
+    /// non-zero once the writer finished.
+    fn fail(&self) {
+        self.failed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Picks the packets that are passed on for `--sample-every` and `--sample-hz`.
+struct Throttle {
+    every: u64,
+    seen: u64,
+    interval: Option<Duration>,
+    /// earliest time the next packet is kept
+    next: Instant,
+}
+impl Throttle {
+    fn keep(&mut self) -> bool {
+        let index = self.seen;
+        self.seen += 1;
+        if !index.is_multiple_of(self.every) {
+            return false;
+        }
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        // keep the average rate even though packets don't arrive exactly on time
+        self.next = (self.next + interval).max(now);
+        true
+    }
+}
+
+/// Notices when no data arrived for `--idle-timeout`.
+struct Idle {
+    timeout: Option<Duration>,
+    exit: bool,
+    /// arrival of the last packet, or start of the capture
+    since: Instant,
+    /// timeouts that passed since then
+    warnings: u32,
+}
+impl Idle {
+    fn packet(&mut self) {
+        self.since = Instant::now();
+        self.warnings = 0;
+    }
+
+    /// Warns each time another timeout passed without data, returns whether the capture should stop.
+    fn check(&mut self) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        let idle = self.since.elapsed();
+        if idle < timeout * (self.warnings + 1) {
+            return false;
+        }
+        self.warnings += 1;
+        warn!("no data for {:.1}s", idle.as_secs_f64());
+        if self.exit {
+            info!("stopping the capture, --exit-on-idle is set");
+        }
+        self.exit
+    }
+}
+
+/// Reports a receive error and returns how long to wait before receiving again, `None` once
+/// --max-recv-errors came in a row and the capture fails.
+fn recv_error(errors: &mut RecvErrors, e: &std::io::Error, limits: &Limits) -> Option<Duration> {
+    match errors.error() {
+        Some(interval) => {
+            warn!("Error receiving message: {e}, retrying in {interval:?}");
+            Some(interval)
+        },
+        None => {
+            error!("Error receiving message: {e}, giving up after {} errors in a row", errors.in_a_row());
+            limits.fail();
+            None
+        },
+    }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+fn receive_udp(socket: &UdpSocket, tx: SyncSender<Packet>, limits: &Limits, buffer_size: usize) {
+    let port = socket.local_addr().map_or(0, |address| address.port());
+    receive_datagrams(SockRef::from(socket), port, tx, limits, buffer_size);
+}
+
+/// Receives on a unix datagram socket, its packets have no sender address or port.
+#[cfg(unix)]
+fn receive_uds(socket: &std::os::unix::net::UnixDatagram, tx: SyncSender<Packet>, limits: &Limits, buffer_size: usize) {
+    receive_datagrams(SockRef::from(socket), 0, tx, limits, buffer_size);
+}
+
+fn receive_datagrams(socket: SockRef<'_>, port: u16, tx: SyncSender<Packet>, limits: &Limits, buffer_size: usize) {
+    let mut buffer = vec![0u8; buffer_size];
+    // truncation is reported once per capture, not for every datagram
+    let mut truncation_reported = false;
+    let mut partial_reported = false;
+    let mut idle = limits.idle();
+    let mut throttle = limits.throttle();
+    let mut deframer = limits.deframer();
+    let mut errors = limits.recv_errors();
+    while !limits.reached() {
+        socket.set_read_timeout(limits.read_timeout()).expect("set_read_timeout call failed");
+        let recv_result = recv_datagram(&socket, &mut buffer);
+        match recv_result {
+            Err(e) if is_timeout(&e) => {
+                if idle.check() {
+                    break;
+                }
+            },
+            Err(e) => match recv_error(&mut errors, &e, limits) {
+                Some(interval) => thread::sleep(interval),
+                None => break,
+            },
+            Ok(received) => {
+                errors.packet();
+                let source = received.source;
+                if !truncation_reported {
+                    truncation_reported = report_truncation(received.len, received.datagram_len, buffer.len(), source);
+                }
+                idle.packet();
+                let records = split_datagram(&mut deframer, &buffer[0..received.len], source, &mut partial_reported);
+                if !forward(records, source, port, received.kernel_time, &mut throttle, limits, |packet| try_send(&tx, packet, limits)) {
+                    break;
+                }
+            },
+        };
+    }
+    report_skipped(&deframer);
+}
+
+/// Splits one datagram into its records, an incomplete record at its end is reported once.
+fn split_datagram(deframer: &mut Deframer, datagram: &[u8], source: SocketAddr, partial_reported: &mut bool) -> Vec<Vec<u8>> {
+    let mut records = deframer.push(datagram);
+    let (last, partial) = deframer.end_datagram();
+    records.extend(last);
+    if partial > 0 && !*partial_reported {
+        warn!("datagram from {source} ended in an incomplete record of {partial} bytes, see --framing");
+        *partial_reported = true;
+    }
+    records
+}
+
+/// Logs how many bytes were dropped while looking for `--sync-word`.
+fn report_skipped(deframer: &Deframer) {
+    if deframer.skipped() > 0 {
+        warn!("discarded {} bytes outside of records starting with the sync word", deframer.skipped());
+    }
+}
+
+/// Hands `packet` to the writer without waiting, it is dropped if the channel is full.
+/// Returns false once the writer is gone.
+fn try_send(tx: &SyncSender<Packet>, packet: Packet, limits: &Limits) -> bool {
+    match tx.try_send(packet) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            limits.drop_packet();
+            true
+        },
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// Passes the records of one read on to the writer with `send`, returns false once the capture has to end.
+/// They arrived now unless the kernel reported when it received them.
+fn forward(records: Vec<Vec<u8>>, source: SocketAddr, port: u16, kernel_time: Option<SystemTime>, throttle: &mut Throttle, limits: &Limits, send: impl Fn(Packet) -> bool) -> bool {
+    let now = SystemTime::now();
+    let time = kernel_time.unwrap_or(now);
+    let instant = Instant::now() - now.duration_since(time).unwrap_or_default();
+    for mut data in records {
+        if !limits.verify(&mut data, source) {
+            continue;
+        }
+        if !throttle.keep() {
+            continue;
+        }
+        if !limits.count_packet() {
+            return false;
+        }
+        if !send(Packet { time, instant, source, port, data }) {
+            // the writer is gone, main reports why once it joined the thread
+            return false;
+        }
+    }
+    true
+}
+
+/// Warns if a datagram did not fit into the buffer, returns whether it was reported.
+fn report_truncation(len: usize, datagram_len: Option<usize>, buffer_len: usize, source: SocketAddr) -> bool {
+    match datagram_len {
+        Some(datagram_len) if datagram_len > len => {
+            warn!("datagram of {datagram_len} bytes from {source} was truncated to {len} bytes, see --buffer-size");
+            true
+        },
+        None if len == buffer_len => {
+            warn!("datagram from {source} filled the {len} byte buffer and may have been truncated, see --buffer-size");
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Datagram received into a buffer.
+struct Received {
+    /// bytes read into the buffer
+    len: usize,
+    /// full length of the datagram if the platform reports it
+    datagram_len: Option<usize>,
+    source: SocketAddr,
+    /// when the kernel received it, for --kernel-timestamp
+    kernel_time: Option<SystemTime>,
+}
+
+/// Receives one datagram into `buffer`.
+#[cfg(target_os = "linux")]
+fn recv_datagram(socket: &Socket, buffer: &mut [u8]) -> std::io::Result<Received> {
+    use std::os::fd::AsRawFd;
+    let mut iov = libc::iovec { iov_base: buffer.as_mut_ptr().cast(), iov_len: buffer.len() };
+    // room for the timespec of SO_TIMESTAMPNS, aligned for the cmsghdr in front of it
+    let mut control = [0u64; 8];
+    // SAFETY: msghdr points to the buffer, the control buffer and the address storage given by
+    // try_init, which all outlive the call, and the control messages are read within msg_controllen
+    let ((datagram_len, kernel_time), source) = unsafe {
+        socket2::SockAddr::try_init(|storage, storage_len| {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = storage.cast();
+            msg.msg_namelen = *storage_len;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+            // with MSG_TRUNC linux returns the real length of a datagram even if it did not fit
+            let datagram_len = libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_TRUNC);
+            if datagram_len < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            *storage_len = msg.msg_namelen;
+            let mut kernel_time = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS {
+                    let time = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::timespec>());
+                    kernel_time = Some(UNIX_EPOCH + Duration::new(time.tv_sec as u64, time.tv_nsec as u32));
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            Ok((datagram_len as usize, kernel_time))
+        })?
+    };
+    Ok(Received {
+        len: datagram_len.min(buffer.len()),
+        datagram_len: Some(datagram_len),
+        source: source.as_socket().unwrap_or(UNADDRESSED),
+        kernel_time,
+    })
+}
+
+/// Receives one datagram into `buffer`.
+#[cfg(not(target_os = "linux"))]
+fn recv_datagram(socket: &Socket, buffer: &mut [u8]) -> std::io::Result<Received> {
+    // SAFETY: the buffer is already initialized and recvfrom only writes bytes into it
+    let uninit = unsafe { &mut *(buffer as *mut [u8] as *mut [std::mem::MaybeUninit<u8>]) };
+    let (len, source) = socket.recv_from(uninit)?;
+    Ok(Received { len, datagram_len: None, source: source.as_socket().unwrap_or(UNADDRESSED), kernel_time: None })
+}
+
+/// Accepts a single connection and forwards whatever each `read` returns as one packet.
+///
+/// TCP has no message boundaries, so without --framing or --sync-word records are split wherever
+/// a read ends and a partial trailing value is dropped.
+fn receive_tcp(listener: &TcpListener, tx: SyncSender<Packet>, limits: &Limits, buffer_size: usize) {
+    // poll for the connection so an interrupt or the deadline can end the wait
+    listener.set_nonblocking(true).expect("set_nonblocking call failed");
+    let (mut stream, peer) = loop {
+        match listener.accept() {
+            Ok(connection) => break connection,
+            Err(e) if is_timeout(&e) => {
+                if limits.reached() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(50));
+            },
+            Err(e) => {
+                error!("Error accepting connection: {e}");
+                limits.fail();
+                return;
+            },
+        };
+    };
+    info!("accepted connection from {peer}");
+    stream.set_nonblocking(false).expect("set_nonblocking call failed");
+    let port = listener.local_addr().map_or(0, |address| address.port());
+
+    let mut buffer = vec![0u8; buffer_size];
+    let mut idle = limits.idle();
+    let mut throttle = limits.throttle();
+    // records can span reads
+    let mut deframer = limits.deframer();
+    while !limits.reached() {
+        stream.set_read_timeout(limits.read_timeout()).expect("set_read_timeout call failed");
+        match stream.read(&mut buffer) {
+            Err(e) if is_timeout(&e) => {
+                if idle.check() {
+                    break;
+                }
+            },
+            // a connection doesn't recover from a failed read
+            Err(e) => {
+                error!("Error receiving message: {e}");
+                limits.fail();
+                break;
+            },
+            Ok(0) => {
+                info!("connection closed by {peer}");
+                break;
+            },
+            Ok(len) => {
+                idle.packet();
+                if !forward(deframer.push(&buffer[0..len]), peer, port, None, &mut throttle, limits, |packet| tx.send(packet).is_ok()) {
+                    break;
+                }
+            },
+        };
+    }
+    // the end of the stream completes the last record after a sync word or of --values-per-row
+    if let (Some(last), _) = deframer.end_datagram() {
+        forward(vec![last], peer, port, None, &mut throttle, limits, |packet| tx.send(packet).is_ok());
+    }
+    report_skipped(&deframer);
+}
+
+/// Passes the packets recorded in `file` on as if they had been received, paced by `pacing`,
+/// until the file ends or a limit is reached.
+fn replay(file: &std::fs::File, pacing: Pacing, tx: SyncSender<Packet>, limits: &Limits) {
+    let source = UNADDRESSED;
+    let mut reader = std::io::BufReader::new(file);
+    let mut partial_reported = false;
+    let mut throttle = limits.throttle();
+    let mut deframer = limits.deframer();
+    let mut replayed = 0;
+    let start = Instant::now();
+    let mut first_recorded = None;
+    while !limits.reached() {
+        let header = match pacing {
+            Pacing::Recorded => reader.read_u64::<BigEndian>().map(Some),
+            _ => Ok(None),
+        };
+        let header = header.and_then(|recorded| Ok((recorded, reader.read_u32::<BigEndian>()? as usize)));
+        let (recorded, len) = match header {
+            Ok(header) => header,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                info!("replayed {replayed} packets, end of input");
+                break;
+            },
+            Err(e) => {
+                error!("Error reading input: {e}");
+                break;
+            },
+        };
+        // a corrupt length or a file that isn't a dump would otherwise allocate up to 4 GiB
+        if len > MAX_RECORD_LEN {
+            error!("input record of {len} bytes exceeds {MAX_RECORD_LEN} bytes, is the input a capture dump?");
+            break;
+        }
+        let mut datagram = vec![0; len];
+        if let Err(e) = reader.read_exact(&mut datagram) {
+            error!("input ends in an incomplete packet of {len} bytes; {e}");
+            break;
+        }
+        let due = match (pacing, recorded) {
+            (Pacing::Rate(interval), _) => Some(start + interval * replayed),
+            (Pacing::Recorded, Some(recorded)) => {
+                let first = *first_recorded.get_or_insert(recorded);
+                Some(start + Duration::from_micros(recorded.saturating_sub(first)))
+            },
+            _ => None,
+        };
+        if due.is_some_and(|due| !wait_until(due, limits)) {
+            break;
+        }
+        replayed += 1;
+        let records = split_datagram(&mut deframer, &datagram, source, &mut partial_reported);
+        if !forward(records, source, 0, None, &mut throttle, limits, |packet| tx.send(packet).is_ok()) {
+            break;
+        }
+    }
+    report_skipped(&deframer);
+}
+
+/// Sleeps until `due` in steps short enough to notice an interrupt or the deadline, false if the
+/// capture has to end first.
+fn wait_until(due: Instant, limits: &Limits) -> bool {
+    loop {
+        if limits.reached() {
+            return false;
+        }
+        let left = due.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+/// Hands everything received to `handle` until the receiving side is done, flushing in between.
+fn writer<T>(rx: Receiver<T>, mut writer: Writer, handle: fn(&mut Writer, T)) {
+    loop {
+        // only wake up without a packet when pending rows have to be flushed or progress reported
+        let recv_result = match writer.due_in() {
+            Some(timeout) => rx.recv_timeout(timeout),
+            None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match recv_result {
+            Err(mpsc::RecvTimeoutError::Timeout) => writer.run_due(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                writer.finish();
+                debug!("recv thread disconnected");
+                return;
+            },
+            Ok(item) => handle(&mut writer, item),
+        };
+    }
+}
+
+/// Layout and decode options given on the command line.
+fn decode_settings(options: &Cli) -> (Layout, DecodeOptions) {
+    let schema = if options.schema.is_empty() { vec![options.data_type] } else { options.schema.clone() };
+    let layout = Layout { header: options.header_schema.clone(), schema };
+    let decode_options = DecodeOptions {
+        endianness: options.endianness,
+        bool_bits: options.bool_bits,
+        bool_order: options.bool_order,
+        fixed_point: options.fixed_point,
+        scale: options.scale,
+        offset: options.offset,
+        hex_spaced: options.hex_spaced,
+        filter_min: options.filter_min,
+        filter_max: options.filter_max,
+        filter_mode: options.filter_mode,
+        pad_records: options.null_value.is_some(),
+        max_values: options.max_values_per_packet.map(|max| max as usize),
+    };
+    (layout, decode_options)
+}
+
+/// Data types of a packet, a header decoded once and then the schema repeated until it ends.
+#[derive(Clone)]
+struct Layout {
+    header: Vec<DataType>,
+    schema: Vec<DataType>,
+}
+impl Layout {
+    /// Splits `data` into the header and the body the schema repeats over, the body is empty if
+    /// the packet ends inside the header.
+    fn split<'a>(&self, data: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        let width: usize = self.header.iter().map(DataType::width).sum();
+        data.split_at(width.min(data.len()))
+    }
+
+    /// Decodes a packet like [`decode_row`], the header and the body each as far as they go.
+    fn decode_row(&self, data: &[u8], options: &DecodeOptions) -> Option<Vec<Value>> {
+        if self.header.is_empty() {
+            return decode_row(data, &self.schema, options);
+        }
+        let (header, body) = self.split(data);
+        let mut values = decode_row(header, &self.header, options)?;
+        values.truncate(options.max_values.unwrap_or(usize::MAX));
+        let max_values = options.max_values.map(|max| max - values.len());
+        values.extend(decode_row(body, &self.schema, &DecodeOptions { max_values, ..*options })?);
+        Some(values)
+    }
+
+    /// Bytes at the end of a packet of `len` bytes that don't fit a whole value, see [`trailing_bytes`].
+    fn trailing_bytes(&self, len: usize) -> usize {
+        let width: usize = self.header.iter().map(DataType::width).sum();
+        match len.checked_sub(width) {
+            Some(body) => trailing_bytes(body, &self.schema),
+            None => trailing_bytes(len, &self.header),
+        }
+    }
+
+    /// Number of values a packet of `len` bytes decodes to, see [`value_count`].
+    fn value_count(&self, len: usize, options: &DecodeOptions) -> usize {
+        let width: usize = self.header.iter().map(DataType::width).sum();
+        match len.checked_sub(width) {
+            Some(body) => self.header_values(options) + value_count(body, &self.schema, options),
+            None => value_count(len, &self.header, options),
+        }
+    }
+
+    /// Data type of the value at `index` of a decoded packet.
+    #[cfg(any(feature = "parquet", feature = "sqlite"))]
+    fn value_type(&self, index: usize, options: &DecodeOptions) -> DataType {
+        match index.checked_sub(self.header_values(options)) {
+            Some(index) => options.value_type(&self.schema, index),
+            None => options.value_type(&self.header, index),
+        }
+    }
+
+    fn header_values(&self, options: &DecodeOptions) -> usize {
+        self.header.iter().map(|&data_type| options.values_per_field(data_type)).sum()
+    }
+
+    fn data_types(&self) -> impl Iterator<Item = &DataType> {
+        self.header.iter().chain(&self.schema)
+    }
+}
+
+/// Number of packets `--endianness auto` looks at before deciding.
+const AUTO_ENDIANNESS_PACKETS: usize = 8;
+
+/// Values in the first row above which the data type is probably wrong, unless --columns names them.
+const MANY_VALUES: usize = 64;
+
+/// Turns packets into rows and writes them out, batched when writing to a file.
+struct Writer {
+    options: Cli,
+    output_file: Option<OutputFile>,
+    csv_string: String,
+    /// rows encoded for --format msgpack, batched like `csv_string`
+    frames: Vec<u8>,
+    count: u64,
+    /// arrival of the oldest row still waiting to be written
+    batch_start: Instant,
+    /// arrival time of the oldest row still waiting, picks the --rotate-interval file it goes to
+    batch_time: SystemTime,
+    first_packet: bool,
+    /// number of packets seen so far, for --index-column
+    index: u64,
+    /// leftover bytes that don't fit the data type are reported once per capture
+    trailing_reported: bool,
+    /// packets cut off by --max-values-per-packet are reported once per capture
+    capped_reported: bool,
+    /// --metadata and the header row, repeated at the top of every rotated file
+    header: Option<String>,
+    layout: Layout,
+    decode_options: DecodeOptions,
+    csv: CsvRows,
+    stats: Stats,
+    /// when to report progress next for --progress-interval
+    next_progress: Option<Instant>,
+    /// reported with the progress
+    kernel_drops: KernelDrops,
+    /// packets held back until `--endianness auto` picked a byte order
+    undetected: Vec<Packet>,
+    /// rows held back for `--transpose`, starting with the header if there is one
+    transposed: Vec<Vec<String>>,
+    /// packets decoded by a worker ahead of one that arrived earlier
+    reorder: BTreeMap<u64, Decoded>,
+    next_sequence: u64,
+    trigger: Option<Trigger>,
+    /// created with the first row for --format parquet
+    #[cfg(feature = "parquet")]
+    parquet: Option<parquet_output::ParquetFile>,
+    /// opened with the first row for --output-sqlite
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<sqlite_output::SqliteFile>,
+}
+impl Writer {
+    fn new(output_file: Option<OutputFile>, options: Cli, kernel_drops: KernelDrops) -> Self {
+        let (layout, decode_options) = decode_settings(&options);
+        let mut builder = csv::WriterBuilder::new();
+        builder.delimiter(options.delimiter as u8)
+            .flexible(true)
+            .quote_style(if options.quote_non_numeric { QuoteStyle::NonNumeric } else { QuoteStyle::Necessary })
+            .terminator(match options.line_ending {
+                LineEnding::Lf => Terminator::Any(b'\n'),
+                LineEnding::Crlf => Terminator::CRLF,
+            });
+        let tokens = CsvTokens {
+            null: options.null_value.clone().unwrap_or_default(),
+            nan: options.float_nan_token.clone(),
+            inf: options.float_inf_token.clone(),
+            neg_inf: options.float_neg_inf_token.clone().expect("filled in from --float-inf-token at startup"),
+        };
+        let csv = CsvRows::new(builder, options.line_ending.as_str(), tokens);
+        let trigger = options.trigger_threshold.map(|threshold| Trigger {
+            threshold,
+            pretrigger: options.pretrigger,
+            posttrigger: options.posttrigger,
+            history: VecDeque::new(),
+            remaining: 0,
+        });
+        let header = (options.metadata && options.sample.is_none()).then(|| metadata(&options));
+        let continues = output_file.as_ref().is_some_and(OutputFile::continues);
+        let next_progress = options.progress_interval.map(|interval| Instant::now() + interval);
+        Writer {
+            options,
+            output_file,
+            csv_string: header.clone().filter(|_| !continues).unwrap_or_default(),
+            frames: Vec::new(),
+            count: 0,
+            batch_start: Instant::now(),
+            batch_time: SystemTime::now(),
+            first_packet: true,
+            index: 0,
+            trailing_reported: false,
+            capped_reported: false,
+            header,
+            layout,
+            decode_options,
+            csv,
+            stats: Stats { packets: 0, rows: 0, values: 0, bytes: 0, start: Instant::now() },
+            next_progress,
+            kernel_drops,
+            undetected: Vec::new(),
+            transposed: Vec::new(),
+            reorder: BTreeMap::new(),
+            next_sequence: 0,
+            trigger,
+            #[cfg(feature = "parquet")]
+            parquet: None,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+        }
+    }
+
+    fn packet(&mut self, packet: Packet) {
+        if let Endianness::Auto = self.decode_options.endianness {
+            self.undetected.push(packet);
+            if self.undetected.len() >= AUTO_ENDIANNESS_PACKETS {
+                self.resolve_endianness();
+            }
+            return;
+        }
+        let values = self.layout.decode_row(&packet.data, &self.decode_options);
+        self.row(packet, values);
+    }
+
+    /// Writes a packet decoded by a worker once all packets that arrived before it are written.
+    fn decoded(&mut self, decoded: Decoded) {
+        self.reorder.insert(decoded.sequence, decoded);
+        while let Some(decoded) = self.reorder.remove(&self.next_sequence) {
+            self.next_sequence += 1;
+            self.row(decoded.packet, decoded.values);
+        }
+    }
+
+    /// Writes the row of a decoded packet, `None` if the filter dropped it.
+    fn row(&mut self, packet: Packet, values: Option<Vec<Value>>) {
+        // a steady stream of packets never lets the writer time out
+        self.report_progress_if_due();
+        let values = values.map(|values| self.select(values));
+        let options = &self.options;
+        let index = self.index;
+        self.index += 1;
+
+        self.stats.packets += 1;
+        self.stats.bytes += packet.data.len() as u64;
+        self.stats.values += values.as_ref().map_or(0, Vec::len) as u64;
+        let trailing = self.layout.trailing_bytes(packet.data.len());
+        if trailing > 0 && !self.trailing_reported {
+            warn!(
+                "packet {index} from {} has {} bytes, the last {trailing} don't fit a whole value and are dropped; does the data type match?",
+                packet.source, packet.data.len(),
+            );
+            self.trailing_reported = true;
+        }
+        if let Some(max) = self.decode_options.max_values.filter(|_| !self.capped_reported) {
+            let count = self.layout.value_count(packet.data.len(), &self.decode_options);
+            if count > max {
+                warn!("packet {index} from {} decodes to {count} values, only the first {max} are written, see --max-values-per-packet", packet.source);
+                self.capped_reported = true;
+            }
+        }
+
+        if options.sample.is_some() {
+            match &values {
+                Some(values) => {
+                    let row: Vec<String> = values.iter().map(|value| self.csv.tokens().field(value)).collect();
+                    let row = row.join(&options.delimiter.to_string());
+                    eprintln!("packet {index} from {}: {} bytes, {} values: {row}", packet.source, packet.data.len(), values.len());
+                },
+                None => eprintln!("packet {index} from {}: {} bytes, dropped by the filter", packet.source, packet.data.len()),
+            }
+            return;
+        }
+
+        match &mut self.trigger {
+            Some(trigger) => {
+                for (index, packet, values) in trigger.gate((index, packet, values)) {
+                    self.write_row(index, packet, values);
+                }
+            },
+            None => self.write_row(index, packet, values),
+        }
+    }
+
+    fn write_row(&mut self, index: u64, packet: Packet, values: Option<Vec<Value>>) {
+        // rows go to the file of the period they arrived in, not of the one they are flushed in
+        let period_ended = self.output_file.as_ref().is_some_and(|output_file| !output_file.same_period(self.batch_time, packet.time));
+        if self.count > 0 && period_ended {
+            self.flush();
+        }
+        if self.count == 0 {
+            self.batch_time = packet.time;
+        }
+        let options = &self.options;
+        let mut leading = Vec::new();
+        if options.index_column {
+            leading.push(Field { name: "index", value: index.to_string(), numeric: true });
+        }
+        let elapsed = packet.instant.saturating_duration_since(self.stats.start);
+        if let Some(timestamp) = format_timestamp(packet.time, elapsed, &options.timestamp) {
+            let numeric = matches!(options.timestamp, Timestamp::UnixMs | Timestamp::MonotonicNs);
+            leading.push(Field { name: "timestamp", value: timestamp, numeric });
+        }
+        if options.source_column {
+            leading.push(Field { name: "source", value: packet.source.to_string(), numeric: false });
+        }
+        if options.port_column {
+            leading.push(Field { name: "port", value: packet.port.to_string(), numeric: true });
+        }
+
+        let Some(values) = values else {
+            debug!("packet {index} from {} dropped, a value is outside the filter range", packet.source);
+            return;
+        };
+        self.stats.rows += 1;
+
+        let first_row = self.first_packet;
+        // the metadata written before the first row is printed with it
+        let tee_start = if first_row { 0 } else { self.csv_string.len() };
+        if self.first_packet {
+            if !options.columns.is_empty() && options.columns.len() != values.len() {
+                error!("{} column names given but the first packet decoded to {} values", options.columns.len(), values.len());
+                std::process::exit(1);
+            }
+            if options.columns.is_empty() && values.len() > MANY_VALUES {
+                let bool_hint = if self.layout.data_types().any(|data_type| matches!(data_type, DataType::Bool)) {
+                    format!(", bool writes {} columns per byte", self.decode_options.bool_bits)
+                } else {
+                    String::new()
+                };
+                warn!("the first packet decoded to {} values, check --data-type or --schema{bool_hint}", values.len());
+            }
+            if options.header && matches!(options.format, Format::Csv) && options.output_sqlite.is_none() {
+                let names = header_row(values.len(), options);
+                if options.transpose {
+                    self.transposed.push(names);
+                } else {
+                    let start = self.csv_string.len();
+                    self.csv.write_record(&names, &mut self.csv_string);
+                    self.header.get_or_insert_default().push_str(&self.csv_string[start..]);
+                    // kept for rotation, but an appended file got its header from an earlier capture
+                    if self.output_file.as_ref().is_some_and(OutputFile::continues) {
+                        self.csv_string.truncate(start);
+                    }
+                }
+            }
+            self.first_packet = false;
+        }
+
+        let count = options.count_column.then(|| {
+            let decoded = values.iter().filter(|value| !matches!(value, Value::Missing)).count();
+            Field { name: "count", value: decoded.to_string(), numeric: true }
+        });
+
+        // every packet is its own row, regardless of when the file gets flushed
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &options.output_sqlite {
+            if self.sqlite.is_none() {
+                let names = header_row(values.len(), options);
+                let columns = sqlite_output::columns(names, &leading, &self.value_types(values.len()), count.as_ref(), &self.decode_options);
+                match sqlite_output::SqliteFile::create(path, &options.table, columns) {
+                    Ok(sqlite) => self.sqlite = Some(sqlite),
+                    Err(e) => write_failed(path, e),
+                }
+            }
+            if let Some(sqlite) = &mut self.sqlite {
+                if let Err(e) = sqlite.push(leading, values, count) {
+                    write_failed(sqlite.path(), e);
+                }
+            }
+            self.count_row();
+            return;
+        }
+        match options.format {
+            Format::Csv if options.transpose => {
+                let row = leading.into_iter().map(|field| field.value)
+                    .chain(values.iter().map(|value| self.csv.tokens().field(value)))
+                    .chain(count.map(|field| field.value));
+                self.transposed.push(row.collect());
+                return;
+            },
+            Format::Csv => {
+                let leading = leading.iter().map(|field| field.value.as_str());
+                let trailing = count.iter().map(|field| field.value.as_str());
+                self.csv.write_row(leading, &values, trailing, &mut self.csv_string);
+            },
+            Format::Jsonl => {
+                json_row(&leading, &values, count.as_ref(), options, &mut self.csv_string);
+                self.csv_string.push_str(options.line_ending.as_str());
+            },
+            #[cfg(feature = "parquet")]
+            Format::Parquet => {
+                if self.parquet.is_none() {
+                    let path = options.output.as_ref().expect("--format parquet requires --output");
+                    let names = header_row(values.len(), options);
+                    match parquet_output::ParquetFile::create(path, names, &leading, &self.value_types(values.len()), count.as_ref(), &self.decode_options) {
+                        Ok(parquet) => self.parquet = Some(parquet),
+                        Err(e) => write_failed(path, e),
+                    }
+                }
+                if let Some(parquet) = &mut self.parquet {
+                    parquet.push(leading, values, count);
+                }
+            },
+            #[cfg(not(feature = "parquet"))]
+            Format::Parquet => unreachable!("--format parquet is rejected at startup without the parquet feature"),
+            #[cfg(feature = "msgpack")]
+            Format::Msgpack => msgpack_output::push_frame(&leading, &values, count.as_ref(), &mut self.frames),
+            #[cfg(not(feature = "msgpack"))]
+            Format::Msgpack => unreachable!("--format msgpack is rejected at startup without the msgpack feature"),
+        }
+
+        if options.tee {
+            print!("{}", &self.csv_string[tee_start..]);
+        }
+        if first_row && self.output_file.is_some() {
+            // the rows of a batch are about as long as the first one
+            let rows = usize::try_from(options.flush_every).unwrap_or(usize::MAX);
+            self.csv_string.reserve(self.csv_string.len().saturating_mul(rows).min(MAX_PREALLOCATED_LEN));
+        }
+        if self.output_file.is_none() && !matches!(options.format, Format::Parquet) {
+            print!("{}", self.csv_string);
+            self.csv_string.clear();
+            self.print_frames();
+            return;
+        }
+        self.count_row();
+    }
+
+    /// Counts a row towards the batch and flushes once it is full or has waited long enough.
+    fn count_row(&mut self) {
+        if self.count == 0 {
+            self.batch_start = Instant::now();
+        }
+        self.count += 1;
+        let interval_elapsed = self.options.flush_interval.is_some_and(|interval| self.batch_start.elapsed() >= interval);
+        if self.count >= self.options.flush_every || interval_elapsed {
+            self.flush();
+        }
+    }
+
+    /// Keeps the values at `--select-columns`, all of them if it isn't given.
+    fn select(&self, values: Vec<Value>) -> Vec<Value> {
+        if self.options.select_columns.is_empty() {
+            return values;
+        }
+        self.options.select_columns.iter()
+            .map(|&position| values.get(position).cloned().unwrap_or(Value::Missing))
+            .collect()
+    }
+
+    /// Data types of the `values` written to a row.
+    #[cfg(any(feature = "parquet", feature = "sqlite"))]
+    fn value_types(&self, values: usize) -> Vec<DataType> {
+        let positions = match self.options.select_columns.as_slice() {
+            [] => (0..values).collect(),
+            positions => positions.to_vec(),
+        };
+        positions.into_iter().map(|position| self.layout.value_type(position, &self.decode_options)).collect()
+    }
+
+    /// Picks the byte order for `--endianness auto` and writes the packets held back for it.
+    fn resolve_endianness(&mut self) {
+        let packets = std::mem::take(&mut self.undetected);
+        // guessed from the bodies only, a header of a few fields hardly tips the balance
+        let data: Vec<&[u8]> = packets.iter().map(|packet| self.layout.split(&packet.data).1).collect();
+        let endianness = detect_endianness(&data, &self.layout.schema, &self.decode_options);
+        info!("guessed {endianness} endian from the first {} packets, set --endianness if that is wrong", packets.len());
+        self.decode_options.endianness = endianness;
+        for packet in packets {
+            self.packet(packet);
+        }
+    }
+
+    /// Writes the rows held back for `--transpose` with every column turned into a row,
+    /// shorter rows leave empty fields.
+    fn write_transposed(&mut self) {
+        let rows = std::mem::take(&mut self.transposed);
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        for column in 0..columns {
+            let record = rows.iter().map(|row| row.get(column).map_or("", String::as_str));
+            self.csv.write_record(record, &mut self.csv_string);
+        }
+    }
+
+    /// Time until pending rows have waited for `--flush-interval` or progress is to be reported,
+    /// `None` if neither is waiting.
+    fn due_in(&self) -> Option<Duration> {
+        let flush = self.options.flush_interval.filter(|_| self.count > 0)
+            .map(|interval| interval.saturating_sub(self.batch_start.elapsed()));
+        let progress = self.next_progress.map(|next| next.saturating_duration_since(Instant::now()));
+        flush.into_iter().chain(progress).min()
+    }
+
+    /// Writes the pending rows once they have waited for `--flush-interval` and reports progress
+    /// once `--progress-interval` passed.
+    fn run_due(&mut self) {
+        if let Some(interval) = self.options.flush_interval {
+            if self.count > 0 && self.batch_start.elapsed() >= interval {
+                self.flush();
+            }
+        }
+        self.report_progress_if_due();
+    }
+
+    /// Logs how far the capture got once `--progress-interval` passed since the last report.
+    fn report_progress_if_due(&mut self) {
+        if self.next_progress.is_none_or(|next| Instant::now() < next) {
+            return;
+        }
+        let mut progress = format!("{} packets received, {} rows written", self.stats.packets, self.stats.rows);
+        if let Some(path) = self.output_path() {
+            if let Ok(metadata) = std::fs::metadata(path) {
+                let _ = write!(progress, ", {} is {} bytes", path.display(), metadata.len());
+            }
+        }
+        if let Some(drops) = self.kernel_drops.count() {
+            let _ = write!(progress, ", {drops} dropped by the kernel");
+        }
+        info!("{progress}");
+        if let (Some(next), Some(interval)) = (&mut self.next_progress, self.options.progress_interval) {
+            // skip the reports missed while busy instead of catching up
+            while *next <= Instant::now() {
+                *next += interval;
+            }
+        }
+    }
+
+    /// File the rows currently go to, `None` for stdout.
+    fn output_path(&self) -> Option<&Path> {
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = &self.parquet {
+            return Some(parquet.path());
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            return Some(sqlite.path());
+        }
+        self.output_file.as_ref().map(OutputFile::path)
+    }
+
+    fn flush(&mut self) {
+        self.count = 0;
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = &mut self.parquet {
+            if let Err(e) = parquet.flush() {
+                write_failed(parquet.path(), e);
+            }
+            return;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &mut self.sqlite {
+            if let Err(e) = sqlite.flush() {
+                write_failed(sqlite.path(), e);
+            }
+            return;
+        }
+        let Some(output_file) = &mut self.output_file else {
+            self.csv_string.clear();
+            self.frames.clear();
+            return;
+        };
+
+        // rotate before writing so a finished file is only left behind once there is more to write
+        match output_file.rotate_if_needed(self.batch_time) {
+            Ok(false) => {},
+            Ok(true) => {
+                if let Some(header) = self.header.as_ref().filter(|_| !output_file.continues()) {
+                    self.csv_string.insert_str(0, header);
+                }
+            },
+            Err(e) => write_failed(output_file.path(), e),
+        }
+        let batch = if self.frames.is_empty() { self.csv_string.as_bytes() } else { &self.frames };
+        if let Err(e) = output_batch(batch, output_file) {
+            write_failed(output_file.path(), e);
+        }
+        self.csv_string.clear();
+        self.frames.clear();
+    }
+
+    /// Writes the rows of --format msgpack to stdout, they aren't text for `print!`.
+    fn print_frames(&mut self) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = stdout.write_all(&self.frames).and_then(|()| stdout.flush()) {
+            error!("Could not write to stdout; {e}");
+            std::process::exit(1);
+        }
+        self.frames.clear();
+    }
+
+    /// Writes whatever is still pending at the end of the capture.
+    fn finish(mut self) {
+        if !self.undetected.is_empty() {
+            self.resolve_endianness();
+        }
+        self.stats.report();
+        if !self.transposed.is_empty() {
+            self.write_transposed();
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = self.parquet.take() {
+            let path = parquet.path().to_owned();
+            if let Err(e) = parquet.finish() {
+                write_failed(&path, e);
+            }
+            return;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = self.sqlite.take() {
+            let path = sqlite.path().to_owned();
+            if let Err(e) = sqlite.finish() {
+                write_failed(&path, e);
+            }
+            return;
+        }
+        if self.output_file.is_none() {
+            print!("{}", self.csv_string);
+            self.print_frames();
+            return;
+        }
+        if !self.csv_string.is_empty() || !self.frames.is_empty() {
+            self.flush();
+        }
+        if let Some(output_file) = self.output_file {
+            let path = output_file.path().to_owned();
+            if let Err(e) = output_file.finish() {
+                write_failed(&path, e);
+            }
+        }
+    }
+}
+
+/// Packet with its index and decoded values, held back until it is clear whether to write it.
+type HeldRow = (u64, Packet, Option<Vec<Value>>);
+
+/// Only lets packets around one with a value above `--trigger-threshold` through, like the
+/// trigger of an oscilloscope.
+struct Trigger {
+    threshold: f64,
+    pretrigger: usize,
+    posttrigger: usize,
+    /// the last `pretrigger` packets
+    history: VecDeque<HeldRow>,
+    /// packets still let through after the last trigger
+    remaining: usize,
+}
+impl Trigger {
+    /// Returns the rows to write now, the history followed by the new row when it triggers.
+    fn gate(&mut self, row: HeldRow) -> Vec<HeldRow> {
+        let threshold = self.threshold;
+        let fires = row.2.as_ref().is_some_and(|values| {
+            values.iter().any(|value| value.as_f64().is_some_and(|value| value > threshold))
+        });
+        if fires {
+            debug!("packet {} triggered", row.0);
+            // triggering again during the post-trigger packets starts them over
+            self.remaining = self.posttrigger;
+            let mut rows: Vec<HeldRow> = self.history.drain(..).collect();
+            rows.push(row);
+            return rows;
+        }
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return vec![row];
+        }
+        if self.pretrigger > 0 {
+            if self.history.len() == self.pretrigger {
+                self.history.pop_front();
+            }
+            self.history.push_back(row);
+        }
+        Vec::new()
+    }
+}
+
+/// Totals reported when the capture ends.
+struct Stats {
+    packets: u64,
+    /// rows that passed the filter and trigger
+    rows: u64,
+    /// values written, without those of dropped rows
+    values: u64,
+    bytes: u64,
+    start: Instant,
+}
+impl Stats {
+    fn report(&self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let average = if self.packets > 0 { self.bytes as f64 / self.packets as f64 } else { 0.0 };
+        let rate = if elapsed > 0.0 { self.packets as f64 / elapsed } else { 0.0 };
+        info!(
+            "captured {} packets with {} values, {} bytes ({average:.1} bytes per packet) in {elapsed:.1}s, {rate:.1} packets/s",
+            self.packets, self.values, self.bytes,
+        );
+    }
+}
+
+/// Most room made for a batch up front, long rows or a large --flush-every grow it as needed.
+const MAX_PREALLOCATED_LEN: usize = 4 << 20;
+
+/// Column written in front of the decoded values.
+struct Field {
+    name: &'static str,
+    value: String,
+    /// written without quotes in json
+    numeric: bool,
+}
+
+/// `#` comment lines describing the capture for `--metadata`.
+fn metadata(options: &Cli) -> String {
+    let mut lines = vec![format!("udp_to_csv {}", env!("CARGO_PKG_VERSION"))];
+    let started = format_timestamp(SystemTime::now(), Duration::ZERO, &Timestamp::Rfc3339).expect("rfc3339 always formats");
+    lines.push(format!("started: {started}"));
+    match (&options.command, &options.input, &options.path) {
+        (Some(Command::Convert { input }), _, _) => lines.push(format!("converted: {}", input.display())),
+        (None, Some(input), _) => lines.push(format!("input: {}", input.display())),
+        (None, None, Some(path)) => lines.push(format!("socket: {}", path.display())),
+        (None, None, None) => {
+            let bind = match (&options.bind, &options.interface) {
+                (Some(bind), _) => bind.clone(),
+                (None, Some(interface)) => interface.clone(),
+                (None, None) if options.ipv6 => Ipv6Addr::UNSPECIFIED.to_string(),
+                (None, None) => Ipv4Addr::UNSPECIFIED.to_string(),
+            };
+            let ports: Vec<String> = options.port.iter().map(u16::to_string).collect();
+            lines.push(format!("transport: {}", options.transport));
+            lines.push(format!("bind: {bind}"));
+            lines.push(format!("port: {}", ports.join(",")));
+        },
+    }
+    if options.schema.is_empty() {
+        lines.push(format!("data type: {}", options.data_type));
+    } else {
+        let schema: Vec<String> = options.schema.iter().map(DataType::to_string).collect();
+        lines.push(format!("schema: {}", schema.join(",")));
+    }
+    if !options.header_schema.is_empty() {
+        let header: Vec<String> = options.header_schema.iter().map(DataType::to_string).collect();
+        lines.push(format!("header schema: {}", header.join(",")));
+    }
+    lines.push(format!("endianness: {}", options.endianness));
+    lines.push(format!("delimiter: {}", options.delimiter.escape_default()));
+    lines.iter().map(|line| format!("# {line}{}", options.line_ending.as_str())).collect()
+}
+
+fn header_row(values: usize, options: &Cli) -> Vec<String> {
+    let mut names = Vec::new();
+    if options.index_column {
+        names.push("index".to_owned());
+    }
+    if !matches!(options.timestamp, Timestamp::None) {
+        names.push("timestamp".to_owned());
+    }
+    if options.source_column {
+        names.push("source".to_owned());
+    }
+    if options.port_column {
+        names.push("port".to_owned());
+    }
+    if !options.columns.is_empty() {
+        names.extend(options.columns.iter().cloned());
+    } else if !options.select_columns.is_empty() {
+        names.extend(options.select_columns.iter().map(|position| format!("col{position}")));
+    } else {
+        for i in 0..values {
+            names.push(format!("col{i}"));
+        }
+    }
+    if options.count_column {
+        names.push("count".to_owned());
+    }
+    names
+}
+
+/// Appends one packet to `out` as a json array, or as an object keyed by `--columns` if given,
+/// without a line break.
+fn json_row(leading: &[Field], values: &[Value], count: Option<&Field>, options: &Cli, out: &mut String) {
+    let keyed = !options.columns.is_empty();
+    let key = |out: &mut String, name: &str| {
+        if keyed {
+            push_json_string(out, name);
+            out.push(':');
+        }
+    };
+    let mut items = 0;
+    let mut separate = |out: &mut String| {
+        if items > 0 {
+            out.push(',');
+        }
+        items += 1;
+    };
+
+    out.push(if keyed { '{' } else { '[' });
+    for field in leading {
+        separate(out);
+        key(out, field.name);
+        push_json_field(out, field);
+    }
+    if keyed {
+        for (name, value) in options.columns.iter().zip(values) {
+            separate(out);
+            key(out, name);
+            push_json_value(out, value);
+        }
+    } else {
+        for value in values {
+            separate(out);
+            push_json_value(out, value);
+        }
+    }
+    if let Some(field) = count {
+        separate(out);
+        key(out, field.name);
+        push_json_field(out, field);
+    }
+    out.push(if keyed { '}' } else { ']' });
+}
+
+fn push_json_field(out: &mut String, field: &Field) {
+    if field.numeric {
+        out.push_str(&field.value);
+    } else {
+        push_json_string(out, &field.value);
+    }
+}
+
+/// Appends a decoded value as a json literal, json has no representation for NaN or infinity.
+fn push_json_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Bool(value) => write!(out, "{value}").expect("writing to a String can't fail"),
+        Value::Hex(text) | Value::Text(text) => push_json_string(out, text),
+        Value::F32(_) | Value::F64(_) if !value.as_f64().is_some_and(f64::is_finite) => out.push_str("null"),
+        Value::Filtered | Value::Missing => out.push_str("null"),
+        value => write!(out, "{value}").expect("writing to a String can't fail"),
+    }
+}
+
+fn push_json_string(quoted: &mut String, s: &str) {
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+}
+
+/// The csv writer takes a single byte as delimiter.
+fn parse_delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() => Ok(c),
+        _ => Err(format!("delimiter must be a single ascii character, got `{s}`")),
+    }
+}
+
+/// Parses a byte pattern like `AA55` or `0xaa55`.
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(format!("expected an even number of hex digits, got `{s}`"));
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("expected hex digits, got `{s}`")))
+        .collect()
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("expected a positive rate, got `{s}`")),
+    }
+}
+
+/// Parses durations like `30s`, `250ms` or `1h30m`, a plain number is taken as seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_owned());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration `{s}`: {e}"));
+    }
+
+    let mut duration = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let unit_end = rest[number_end..].find(|c: char| c.is_ascii_digit()).map_or(rest.len(), |i| number_end + i);
+        let number: f64 = rest[..number_end].parse().map_err(|_| format!("invalid duration `{s}`"))?;
+        let unit = match rest[number_end..unit_end].trim() {
+            "ms" => 0.001,
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            unit => return Err(format!("unknown time unit `{unit}` in `{s}`")),
+        };
+        duration += Duration::try_from_secs_f64(number * unit).map_err(|e| format!("invalid duration `{s}`: {e}"))?;
+        rest = &rest[unit_end..];
+    }
+    Ok(duration)
+}
+
+/// Formats the arrival `time` of a packet, or for monotonic_ns the time `elapsed` since the capture started.
+fn format_timestamp(time: SystemTime, elapsed: Duration, format: &Timestamp) -> Option<String> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    match format {
+        Timestamp::None => None,
+        Timestamp::UnixMs => Some(since_epoch.as_millis().to_string()),
+        Timestamp::MonotonicNs => Some(elapsed.as_nanos().to_string()),
+        Timestamp::Rfc3339 => {
+            let secs = since_epoch.as_secs();
+            let (year, month, day) = civil_from_days((secs / 86400) as i64);
+            let secs_of_day = secs % 86400;
+            Some(format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+                year, month, day,
+                secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60,
+                since_epoch.subsec_millis(),
+            ))
+        },
+    }
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date in the proleptic gregorian calendar.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Ends the capture with a non-zero status after the writer died, instead of a second panic.
+fn writer_panicked(panic: Box<dyn std::any::Any + Send>) -> ! {
+    let reason = panic.downcast_ref::<&str>().copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown reason");
+    error!("Writing stopped unexpectedly; {reason}");
+    std::process::exit(1);
+}
+
+/// Ends the capture once the output file can't be written, there is nowhere left to put the rows.
+fn write_failed(output: &Path, e: std::io::Error) -> ! {
+    error!("Could not write to output file {}; {e}", output.display());
+    std::process::exit(1);
 }
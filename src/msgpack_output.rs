@@ -0,0 +1,72 @@
+//! MessagePack output, enabled by the `msgpack` feature.
+//!
+//! Every row is one array of the leading fields, the values and the count, prefixed with its
+//! length as a big-endian u32 so it reads back with `--framing u32-length`.
+
+use rmp::encode;
+use udp_to_csv::Value;
+
+use crate::Field;
+
+/// Appends one row to `out` as a length-prefixed array.
+pub fn push_frame(leading: &[Field], values: &[Value], count: Option<&Field>, out: &mut Vec<u8>) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    write_row(leading, values, count, out).expect("writing to a Vec can't fail");
+    let len = (out.len() - start - 4) as u32;
+    out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn write_row(leading: &[Field], values: &[Value], count: Option<&Field>, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let len = leading.len() + values.len() + usize::from(count.is_some());
+    encode::write_array_len(out, len as u32)?;
+    for field in leading {
+        write_field(field, out)?;
+    }
+    for value in values {
+        write_value(value, out)?;
+    }
+    if let Some(field) = count {
+        write_field(field, out)?;
+    }
+    Ok(())
+}
+
+/// Writes a numeric field as the narrowest of integer and float it parses as, anything else as
+/// a string.
+fn write_field(field: &Field, out: &mut Vec<u8>) -> std::io::Result<()> {
+    if field.numeric {
+        if let Ok(value) = field.value.parse::<u64>() {
+            encode::write_uint(out, value)?;
+            return Ok(());
+        }
+        if let Ok(value) = field.value.parse::<i64>() {
+            encode::write_sint(out, value)?;
+            return Ok(());
+        }
+        if let Ok(value) = field.value.parse::<f64>() {
+            return Ok(encode::write_f64(out, value)?);
+        }
+    }
+    Ok(encode::write_str(out, &field.value)?)
+}
+
+/// Writes a value with its own type, a filtered or missing value is nil.
+fn write_value(value: &Value, out: &mut Vec<u8>) -> std::io::Result<()> {
+    match value {
+        Value::Bool(value) => encode::write_bool(out, *value)?,
+        Value::U8(value) =>   { encode::write_uint(out, (*value).into())?; },
+        Value::U16(value) =>  { encode::write_uint(out, (*value).into())?; },
+        Value::U32(value) =>  { encode::write_uint(out, (*value).into())?; },
+        Value::U64(value) =>  { encode::write_uint(out, *value)?; },
+        Value::I8(value) =>   { encode::write_sint(out, (*value).into())?; },
+        Value::I16(value) =>  { encode::write_sint(out, (*value).into())?; },
+        Value::I32(value) =>  { encode::write_sint(out, (*value).into())?; },
+        Value::I64(value) =>  { encode::write_sint(out, *value)?; },
+        Value::F32(value) =>  encode::write_f32(out, *value)?,
+        Value::F64(value) =>  encode::write_f64(out, *value)?,
+        Value::Hex(text) | Value::Text(text) => encode::write_str(out, text)?,
+        Value::Filtered | Value::Missing => encode::write_nil(out)?,
+    }
+    Ok(())
+}
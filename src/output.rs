@@ -0,0 +1,269 @@
+//! The output file that buffered rows are written to in batches.
+
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use log::{info, warn};
+
+use crate::{civil_from_days, Cli, Compression};
+
+/// Output file kept open for the whole capture, or until it is rotated.
+pub struct OutputFile {
+    /// path given by --output
+    output: PathBuf,
+    /// path of the current --rotate-interval period, files rotated by size are numbered after it
+    base: PathBuf,
+    /// file currently written to
+    path: PathBuf,
+    append: bool,
+    compress: Compression,
+    max_size: Option<u64>,
+    rotate_interval: Option<Duration>,
+    /// end of the current --rotate-interval period
+    period_end: Option<SystemTime>,
+    rotations: u32,
+    /// sync every batch to disk for --fsync
+    fsync: bool,
+    /// a named pipe is kept open as one stream and never rotated
+    fifo: bool,
+    /// the reader of the FIFO went away, batches are dropped until another one opens it
+    reader_gone: bool,
+    /// --append found the current file with content, it already starts with a header
+    continued: bool,
+    sink: Sink,
+}
+
+enum Sink {
+    Plain(BufWriter<File>),
+    /// a single gzip stream so every batch goes into the same member
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl OutputFile {
+    pub fn open(output: &Path, options: &Cli) -> std::io::Result<Self> {
+        let fifo = is_fifo(output);
+        if fifo && (options.rotate_interval.is_some() || options.max_file_size.is_some()) {
+            return Err(std::io::Error::other("a FIFO can't be rotated"));
+        }
+        if fifo {
+            // opening a FIFO for writing blocks until the other end is opened for reading
+            info!("waiting for a reader to open {}", output.display());
+        }
+        let (base, period_end) = match options.rotate_interval {
+            Some(interval) => {
+                let (start, end) = period(SystemTime::now(), interval);
+                (timed_path(output, start, interval), Some(end))
+            },
+            None => (output.to_owned(), None),
+        };
+        let continued = options.append && has_content(&base);
+        let sink = open_sink(&base, options.append, options.compress, fifo)?;
+        Ok(OutputFile {
+            output: output.to_owned(),
+            path: base.clone(),
+            base,
+            append: options.append,
+            compress: options.compress,
+            max_size: options.max_file_size,
+            rotate_interval: options.rotate_interval,
+            period_end,
+            rotations: 0,
+            fsync: options.fsync,
+            fifo,
+            reader_gone: false,
+            continued,
+            sink,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether rows are appended to a file an earlier capture left content in, so the header
+    /// and metadata at its top aren't written again.
+    pub fn continues(&self) -> bool {
+        self.continued
+    }
+
+    /// Whether rows that arrived at `a` and `b` go to the file of the same `--rotate-interval`
+    /// period, always without rotation by time.
+    pub fn same_period(&self, a: SystemTime, b: SystemTime) -> bool {
+        self.rotate_interval.is_none_or(|interval| period(a, interval) == period(b, interval))
+    }
+
+    /// Starts a new file if the `--rotate-interval` period ended before rows that arrived at `time`
+    /// or the current file grew past `--max-file-size`, returns whether it did.
+    pub fn rotate_if_needed(&mut self, time: SystemTime) -> std::io::Result<bool> {
+        if let (Some(interval), Some(period_end)) = (self.rotate_interval, self.period_end) {
+            if time >= period_end {
+                let (start, end) = period(time, interval);
+                self.base = timed_path(&self.output, start, interval);
+                self.period_end = Some(end);
+                self.rotations = 0;
+                self.switch_to(self.base.clone())?;
+                return Ok(true);
+            }
+        }
+        if self.is_full()? {
+            self.rotations += 1;
+            self.switch_to(numbered_path(&self.base, self.rotations))?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Whether the file on disk has grown past `--max-file-size`.
+    fn is_full(&self) -> std::io::Result<bool> {
+        let Some(max_size) = self.max_size else {
+            return Ok(false);
+        };
+        let file = match &self.sink {
+            Sink::Plain(file) => file.get_ref(),
+            Sink::Gzip(encoder) => encoder.get_ref().get_ref(),
+        };
+        Ok(file.metadata()?.len() > max_size)
+    }
+
+    /// Completes the current file and continues in `path`.
+    fn switch_to(&mut self, path: PathBuf) -> std::io::Result<()> {
+        self.continued = self.append && has_content(&path);
+        let sink = open_sink(&path, self.append, self.compress, self.fifo)?;
+        let previous = std::mem::replace(&mut self.sink, sink);
+        self.path = path;
+        finish_sink(previous)
+    }
+
+    /// Completes the file, a gzip stream needs its trailer written.
+    pub fn finish(self) -> std::io::Result<()> {
+        match finish_sink(self.sink) {
+            Err(e) if self.fifo && e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+fn has_content(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0)
+}
+
+fn open_sink(path: &Path, append: bool, compress: Compression, fifo: bool) -> std::io::Result<Sink> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    // batches go straight into a FIFO so a failed write leaves nothing behind for the next reader
+    let file = if fifo { BufWriter::with_capacity(0, file) } else { BufWriter::new(file) };
+    Ok(match compress {
+        Compression::None => Sink::Plain(file),
+        Compression::Gzip => Sink::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+    })
+}
+
+fn finish_sink(sink: Sink) -> std::io::Result<()> {
+    match sink {
+        Sink::Plain(mut file) => file.flush(),
+        Sink::Gzip(encoder) => encoder.finish().and_then(|mut file| file.flush()),
+    }
+}
+
+/// Inserts `.{number}` before the extension, `out.csv` becomes `out.1.csv` and `out.csv.gz` becomes `out.1.csv.gz`.
+fn numbered_path(path: &Path, number: u32) -> PathBuf {
+    with_suffix(path, &format!(".{number}"))
+}
+
+/// Inserts the start of the rotation period before the extension, e.g. `out-2024-06-01T13.csv`
+/// for hourly files, with as much of the time as the interval needs to keep names unique.
+fn timed_path(path: &Path, start: SystemTime, interval: Duration) -> PathBuf {
+    let secs = start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+    let interval = interval.as_secs();
+    let mut suffix = format!("-{year:04}-{month:02}-{day:02}");
+    if !interval.is_multiple_of(86400) {
+        suffix += &format!("T{hour:02}");
+        if !interval.is_multiple_of(3600) {
+            suffix += &format!("{minute:02}");
+            if !interval.is_multiple_of(60) {
+                suffix += &format!("{second:02}");
+            }
+        }
+    }
+    with_suffix(path, &suffix)
+}
+
+/// Start and end of the period of length `interval` that `time` falls in, periods are aligned to
+/// whole multiples of the interval since the unix epoch so hourly files start on the hour.
+fn period(time: SystemTime, interval: Duration) -> (SystemTime, SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let interval_secs = interval.as_secs().max(1);
+    let start = UNIX_EPOCH + Duration::from_secs(since_epoch - since_epoch % interval_secs);
+    (start, start + Duration::from_secs(interval_secs))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut extension_start = name.rfind('.').filter(|&i| i > 0).unwrap_or(name.len());
+    // keep a compression extension together with the one before it
+    if name[extension_start..] == *".gz" {
+        extension_start = name[..extension_start].rfind('.').filter(|&i| i > 0).unwrap_or(extension_start);
+    }
+    path.with_file_name(format!("{}{suffix}{}", &name[..extension_start], &name[extension_start..]))
+}
+
+/// Writes a batch of rows, a FIFO without a reader drops it with a warning.
+pub fn output_batch(batch: &[u8], output: &mut OutputFile) -> std::io::Result<()> {
+    match write_batch(batch, output) {
+        Err(e) if output.fifo && e.kind() == std::io::ErrorKind::BrokenPipe => {
+            if !output.reader_gone {
+                warn!("no reader on {}, dropping rows until one opens it", output.path.display());
+                output.reader_gone = true;
+            }
+            Ok(())
+        },
+        Ok(()) if output.reader_gone => {
+            info!("a reader opened {}, writing rows again", output.path.display());
+            output.reader_gone = false;
+            Ok(())
+        },
+        result => result,
+    }
+}
+
+fn write_batch(batch: &[u8], output: &mut OutputFile) -> std::io::Result<()> {
+    let file = match &mut output.sink {
+        Sink::Plain(file) => {
+            file.write_all(batch)?;
+            file.flush()?;
+            file.get_ref()
+        },
+        // a sync flush ends the compressed block so the batch reaches the file
+        Sink::Gzip(encoder) => {
+            encoder.write_all(batch)?;
+            encoder.flush()?;
+            encoder.get_ref().get_ref()
+        },
+    };
+    if output.fsync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
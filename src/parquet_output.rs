@@ -0,0 +1,165 @@
+//! Parquet output, enabled by the `parquet` feature.
+//!
+//! Rows are collected in memory and written as one row group per flush. The columns are fixed
+//! by the first row, so the file is only created once there is one.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::types::{Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type, UInt64Type, UInt8Type};
+use arrow_array::{ArrayRef, ArrowPrimitiveType, BooleanArray, PrimitiveArray, RecordBatch, StringArray};
+use arrow_schema::{DataType as ArrowType, Field as ArrowField, Schema};
+use log::warn;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use udp_to_csv::{DataType, DecodeOptions, Value};
+
+use crate::Field;
+
+pub struct ParquetFile {
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    /// number of columns in front of the values
+    leading: usize,
+    value_types: Vec<ArrowType>,
+    /// fields apart from the values of the rows since the last flush, leading ones first
+    fields: Vec<Vec<String>>,
+    values: Vec<Vec<Value>>,
+    /// a row with more values than the first one is reported once
+    overflow_reported: bool,
+}
+
+impl ParquetFile {
+    /// Creates the file with a column for each of `names`, typed after the first row: `leading`
+    /// and `trailing` fields and its values, decoded as `value_types`.
+    pub fn create(path: &Path, names: Vec<String>, leading: &[Field], value_types: &[DataType], trailing: Option<&Field>, options: &DecodeOptions) -> std::io::Result<Self> {
+        let value_types: Vec<ArrowType> = value_types.iter()
+            .map(|&data_type| arrow_type(data_type, options))
+            .collect();
+        let types = leading.iter().map(field_type)
+            .chain(value_types.iter().cloned())
+            .chain(trailing.map(field_type));
+        let columns: Vec<ArrowField> = names.into_iter().zip(types).map(|(name, data_type)| ArrowField::new(name, data_type, true)).collect();
+        let schema = Arc::new(Schema::new(columns));
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = ArrowWriter::try_new(File::create(path)?, Arc::clone(&schema), Some(properties)).map_err(std::io::Error::other)?;
+        Ok(ParquetFile {
+            path: path.to_owned(),
+            writer,
+            schema,
+            leading: leading.len(),
+            value_types,
+            fields: Vec::new(),
+            values: Vec::new(),
+            overflow_reported: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds a row, values beyond the columns of the first row are dropped and missing ones are null.
+    pub fn push(&mut self, leading: Vec<Field>, mut values: Vec<Value>, trailing: Option<Field>) {
+        if values.len() > self.value_types.len() {
+            if !self.overflow_reported {
+                warn!("a row has {} values but the parquet file only has columns for {}, the rest is dropped", values.len(), self.value_types.len());
+                self.overflow_reported = true;
+            }
+            values.truncate(self.value_types.len());
+        }
+        self.fields.push(leading.into_iter().chain(trailing).map(|field| field.value).collect());
+        self.values.push(values);
+    }
+
+    /// Writes the rows since the last flush as a row group.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.values.is_empty() {
+            return Ok(());
+        }
+        let fields = std::mem::take(&mut self.fields);
+        let values = std::mem::take(&mut self.values);
+        let trailing_types = &self.schema.fields()[self.leading + self.value_types.len()..];
+        let leading = self.schema.fields()[..self.leading].iter().enumerate()
+            .map(|(column, field)| field_array(&fields, column, field.data_type()));
+        let value_arrays = self.value_types.iter().enumerate()
+            .map(|(column, data_type)| value_array(&values, column, data_type));
+        let trailing = trailing_types.iter().enumerate()
+            .map(|(column, field)| field_array(&fields, self.leading + column, field.data_type()));
+        let columns = leading.chain(value_arrays).chain(trailing).collect();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns).map_err(std::io::Error::other)?;
+        self.writer.write(&batch).map_err(std::io::Error::other)?;
+        self.writer.flush().map_err(std::io::Error::other)
+    }
+
+    /// Writes the pending rows and the footer that makes the file readable.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.flush()?;
+        self.writer.close().map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Column type of a decoded value, numbers that are scaled or in Q format become `Float64`.
+fn arrow_type(data_type: DataType, options: &DecodeOptions) -> ArrowType {
+    let real = options.scale.is_some() || options.offset.is_some();
+    match data_type {
+        DataType::Bool => ArrowType::Boolean,
+        DataType::Hex | DataType::Ascii => ArrowType::Utf8,
+        DataType::F32 if !real => ArrowType::Float32,
+        DataType::F32 | DataType::F64 => ArrowType::Float64,
+        _ if real || options.fixed_point.is_some() => ArrowType::Float64,
+        DataType::U8 =>  ArrowType::UInt8,
+        DataType::U16 => ArrowType::UInt16,
+        DataType::U24 | DataType::U32 => ArrowType::UInt32,
+        DataType::U64 => ArrowType::UInt64,
+        DataType::I8 =>  ArrowType::Int8,
+        DataType::I16 => ArrowType::Int16,
+        DataType::I24 | DataType::I32 => ArrowType::Int32,
+        DataType::I64 => ArrowType::Int64,
+    }
+}
+
+/// Numeric fields like the index or a unix timestamp are integers, the rest is text.
+fn field_type(field: &Field) -> ArrowType {
+    if field.numeric { ArrowType::Int64 } else { ArrowType::Utf8 }
+}
+
+fn field_array(rows: &[Vec<String>], column: usize, data_type: &ArrowType) -> ArrayRef {
+    let fields = rows.iter().map(|row| row.get(column));
+    match data_type {
+        ArrowType::Int64 => Arc::new(fields.map(|field| field?.parse::<i64>().ok()).collect::<PrimitiveArray<Int64Type>>()),
+        _ => Arc::new(fields.collect::<StringArray>()),
+    }
+}
+
+fn value_array(rows: &[Vec<Value>], column: usize, data_type: &ArrowType) -> ArrayRef {
+    match data_type {
+        ArrowType::Boolean => Arc::new(rows.iter().map(|row| match row.get(column) {
+            Some(Value::Bool(value)) => Some(*value),
+            _ => None,
+        }).collect::<BooleanArray>()),
+        ArrowType::Utf8 => Arc::new(rows.iter().map(|row| match row.get(column) {
+            Some(Value::Hex(text) | Value::Text(text)) => Some(text.as_str()),
+            _ => None,
+        }).collect::<StringArray>()),
+        ArrowType::UInt8 =>  primitive::<UInt8Type>(rows, column, |value| match value { Value::U8(value) => Some(*value), _ => None }),
+        ArrowType::UInt16 => primitive::<UInt16Type>(rows, column, |value| match value { Value::U16(value) => Some(*value), _ => None }),
+        ArrowType::UInt32 => primitive::<UInt32Type>(rows, column, |value| match value { Value::U32(value) => Some(*value), _ => None }),
+        ArrowType::UInt64 => primitive::<UInt64Type>(rows, column, |value| match value { Value::U64(value) => Some(*value), _ => None }),
+        ArrowType::Int8 =>   primitive::<Int8Type>(rows, column, |value| match value { Value::I8(value) => Some(*value), _ => None }),
+        ArrowType::Int16 =>  primitive::<Int16Type>(rows, column, |value| match value { Value::I16(value) => Some(*value), _ => None }),
+        ArrowType::Int32 =>  primitive::<Int32Type>(rows, column, |value| match value { Value::I32(value) => Some(*value), _ => None }),
+        ArrowType::Int64 =>  primitive::<Int64Type>(rows, column, |value| match value { Value::I64(value) => Some(*value), _ => None }),
+        ArrowType::Float32 => primitive::<Float32Type>(rows, column, |value| match value { Value::F32(value) => Some(*value), _ => None }),
+        _ => primitive::<Float64Type>(rows, column, |value| match value { Value::F64(value) => Some(*value), _ => None }),
+    }
+}
+
+/// Builds a column from the values `get` accepts, anything else is null.
+fn primitive<T: ArrowPrimitiveType>(rows: &[Vec<Value>], column: usize, get: impl Fn(&Value) -> Option<T::Native>) -> ArrayRef {
+    Arc::new(rows.iter().map(|row| row.get(column).and_then(&get)).collect::<PrimitiveArray<T>>())
+}
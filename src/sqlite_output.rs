@@ -0,0 +1,134 @@
+//! SQLite output, enabled by the `sqlite` feature.
+//!
+//! Rows are inserted inside a transaction that is committed on every flush. The table is
+//! created with the columns of the first row unless it already exists.
+
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+use udp_to_csv::{DataType, DecodeOptions, Value};
+
+use crate::Field;
+
+pub struct SqliteFile {
+    path: PathBuf,
+    connection: Connection,
+    insert: String,
+    /// number of columns including the leading and trailing fields, fixed by the first row
+    columns: usize,
+    /// a row with more values than the first one is reported once
+    overflow_reported: bool,
+}
+
+impl SqliteFile {
+    /// Opens the database and creates `table` with `columns` of names and types as returned
+    /// by [`columns`].
+    pub fn create(path: &Path, table: &str, columns: Vec<(String, &'static str)>) -> std::io::Result<Self> {
+        let names: Vec<String> = columns.iter().map(|(name, _)| quote(name)).collect();
+        let definitions: Vec<String> = columns.iter().map(|(name, sql_type)| format!("{} {sql_type}", quote(name))).collect();
+        let placeholders = vec!["?"; columns.len()].join(", ");
+
+        let connection = Connection::open(path).map_err(std::io::Error::other)?;
+        connection.execute_batch(&format!("CREATE TABLE IF NOT EXISTS {} ({}); BEGIN;", quote(table), definitions.join(", ")))
+            .map_err(std::io::Error::other)?;
+        Ok(SqliteFile {
+            path: path.to_owned(),
+            connection,
+            insert: format!("INSERT INTO {} ({}) VALUES ({placeholders})", quote(table), names.join(", ")),
+            columns: columns.len(),
+            overflow_reported: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Inserts a row, values beyond the columns of the first row are dropped and missing ones are null.
+    pub fn push(&mut self, leading: Vec<Field>, mut values: Vec<Value>, trailing: Option<Field>) -> std::io::Result<()> {
+        let columns = self.columns - leading.len() - usize::from(trailing.is_some());
+        if values.len() > columns {
+            if !self.overflow_reported {
+                warn!("a row has {} values but the table only has columns for {columns}, the rest is dropped", values.len());
+                self.overflow_reported = true;
+            }
+            values.truncate(columns);
+        }
+        values.resize(columns, Value::Missing);
+        let row: Vec<SqlValue> = leading.iter().map(field_value)
+            .chain(values.into_iter().map(sql_value))
+            .chain(trailing.iter().map(field_value))
+            .collect();
+        let mut insert = self.connection.prepare_cached(&self.insert).map_err(std::io::Error::other)?;
+        insert.execute(rusqlite::params_from_iter(row)).map_err(std::io::Error::other)?;
+        Ok(())
+    }
+
+    /// Commits the rows inserted since the last flush.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.connection.execute_batch("COMMIT; BEGIN;").map_err(std::io::Error::other)
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        self.connection.execute_batch("COMMIT;").map_err(std::io::Error::other)?;
+        self.connection.close().map_err(|(_, e)| std::io::Error::other(e))
+    }
+}
+
+/// Names the columns of `names` and types them after the first row: `leading` and `trailing`
+/// fields and its values, decoded as `value_types`.
+pub fn columns(names: Vec<String>, leading: &[Field], value_types: &[DataType], trailing: Option<&Field>, options: &DecodeOptions) -> Vec<(String, &'static str)> {
+    let value_types = value_types.iter().map(|&data_type| sql_type(data_type, options));
+    let types = leading.iter().map(field_type).chain(value_types).chain(trailing.map(field_type));
+    names.into_iter().zip(types).collect()
+}
+
+/// Quotes an identifier, so column names from --columns can be anything.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Column type of a decoded value, numbers that are scaled or in Q format are `REAL`.
+fn sql_type(data_type: DataType, options: &DecodeOptions) -> &'static str {
+    let real = options.scale.is_some() || options.offset.is_some() || options.fixed_point.is_some();
+    match data_type {
+        DataType::Bool => "INTEGER",
+        DataType::Hex | DataType::Ascii => "TEXT",
+        DataType::F32 | DataType::F64 => "REAL",
+        _ if real => "REAL",
+        _ => "INTEGER",
+    }
+}
+
+/// Numeric fields like the index or a unix timestamp are integers, the rest is text.
+fn field_type(field: &Field) -> &'static str {
+    if field.numeric { "INTEGER" } else { "TEXT" }
+}
+
+fn field_value(field: &Field) -> SqlValue {
+    match field.value.parse::<i64>() {
+        Ok(value) if field.numeric => SqlValue::Integer(value),
+        _ => SqlValue::Text(field.value.clone()),
+    }
+}
+
+fn sql_value(value: Value) -> SqlValue {
+    match value {
+        Value::Bool(value) => SqlValue::Integer(value.into()),
+        Value::U8(value) =>  SqlValue::Integer(value.into()),
+        Value::U16(value) => SqlValue::Integer(value.into()),
+        Value::U32(value) => SqlValue::Integer(value.into()),
+        // sqlite integers are signed 64 bit
+        Value::U64(value) => i64::try_from(value).map_or(SqlValue::Real(value as f64), SqlValue::Integer),
+        Value::I8(value) =>  SqlValue::Integer(value.into()),
+        Value::I16(value) => SqlValue::Integer(value.into()),
+        Value::I32(value) => SqlValue::Integer(value.into()),
+        Value::I64(value) => SqlValue::Integer(value),
+        Value::F32(value) => SqlValue::Real(value.into()),
+        Value::F64(value) => SqlValue::Real(value),
+        Value::Hex(text) | Value::Text(text) => SqlValue::Text(text),
+        Value::Filtered | Value::Missing => SqlValue::Null,
+    }
+}
@@ -0,0 +1,1437 @@
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn free_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("udp_to_csv_{}_{name}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn spawn(port: u16, args: &[&str]) -> Child {
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &port.to_string()])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start udp_to_csv");
+    // give the process time to bind before sending
+    thread::sleep(Duration::from_millis(300));
+    child
+}
+
+fn send(port: u16, packets: &[&[u8]]) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for packet in packets {
+        socket.send_to(packet, ("127.0.0.1", port)).unwrap();
+        thread::sleep(Duration::from_micros(200));
+    }
+}
+
+fn stop(mut child: Child) {
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+fn wait_for_file(path: &PathBuf) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Ok(content) = fs::read_to_string(path) {
+            if !content.is_empty() {
+                return content;
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("no output written to {}", path.display());
+}
+
+#[test]
+fn empty_datagram_does_not_eat_previous_row() {
+    let port = free_port();
+    let output = temp_file("empty.csv");
+    let child = spawn(port, &["--output", output.to_str().unwrap()]);
+
+    // the file is flushed every 1000 packets
+    let mut packets: Vec<&[u8]> = Vec::new();
+    for _ in 0..500 {
+        packets.push(&[]);
+        packets.push(&[0, 1, 0, 2]);
+    }
+    send(port, &packets);
+
+    let content = wait_for_file(&output);
+    stop(child);
+    let _ = fs::remove_file(&output);
+
+    let rows: Vec<&str> = content.lines().collect();
+    assert_eq!(rows.len(), 1000);
+    for pair in rows.chunks(2) {
+        assert_eq!(pair, ["", "1,2"]);
+    }
+}
+
+#[test]
+fn every_data_type_is_decoded_from_a_datagram() {
+    let port = free_port();
+    let child = spawn(port, &[
+        "--schema", "bool,u8,u16,u24,u32,u64,i8,i16,i24,i32,i64,f32,f64,hex", "--bool-bits", "4", "--max-packets", "1",
+    ]);
+    let mut packet = vec![0b0000_0101, 200];
+    packet.extend(0x1234u16.to_be_bytes());
+    packet.extend([0x01, 0x00, 0x00]);
+    packet.extend(70_000u32.to_be_bytes());
+    packet.extend(u64::MAX.to_be_bytes());
+    packet.extend((-5i8).to_be_bytes());
+    packet.extend((-300i16).to_be_bytes());
+    packet.extend([0xff, 0xff, 0xfe]);
+    packet.extend((-70_000i32).to_be_bytes());
+    packet.extend(i64::MIN.to_be_bytes());
+    packet.extend(1.5f32.to_be_bytes());
+    packet.extend((-0.25f64).to_be_bytes());
+    packet.extend([0xde, 0xad]);
+    send(port, &[&packet]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1,0,1,0,200,4660,65536,70000,18446744073709551615,-5,-300,-2,-70000,-9223372036854775808,1.5,-0.25,dead\n",
+    );
+}
+
+#[test]
+fn file_output_has_one_row_per_packet() {
+    let port = free_port();
+    let output = temp_file("rows.csv");
+    let child = spawn(port, &["--output", output.to_str().unwrap(), "--data-type", "u8"]);
+
+    let packets: Vec<[u8; 2]> = (0..1000u16).map(|i| [(i % 256) as u8, 7]).collect();
+    let packets: Vec<&[u8]> = packets.iter().map(|p| &p[..]).collect();
+    send(port, &packets);
+
+    let content = wait_for_file(&output);
+    stop(child);
+    let _ = fs::remove_file(&output);
+
+    let rows: Vec<&str> = content.lines().collect();
+    assert_eq!(rows.len(), 1000);
+    for (i, row) in rows.iter().enumerate() {
+        assert_eq!(*row, format!("{},7", i % 256));
+    }
+}
+
+#[test]
+fn tcp_output_is_flushed_when_connection_closes() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let output = temp_file("tcp.csv");
+    let child = spawn(port, &["--transport", "tcp", "--data-type", "u8", "--output", output.to_str().unwrap()]);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(&[1, 2, 3]).unwrap();
+    drop(stream);
+
+    let status = child.wait_with_output().unwrap().status;
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "1,2,3\n");
+    let _ = fs::remove_file(&output);
+}
+
+#[test]
+fn tcp_reset_fails_after_writing_the_rows() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let child = spawn(port, &["--transport", "tcp", "--data-type", "u8"]);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(&[1, 2]).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // closing with a zero linger time resets the connection
+    socket2::SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+    drop(stream);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Error receiving message"));
+}
+
+#[test]
+fn values_per_row_cuts_a_stream_into_rows() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let child = spawn(port, &["--transport", "tcp", "--data-type", "u8", "--values-per-row", "2"]);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(&[1, 2, 3]).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    stream.write_all(&[4, 5]).unwrap();
+    drop(stream);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3,4\n5\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u16", "--values-per-row", "2", "--max-packets", "2"]);
+    send(port, &[&[0, 1, 0, 2, 0, 3]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3\n");
+}
+
+#[test]
+fn max_packets_flushes_and_exits() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--max-packets", "2"]);
+    send(port, &[&[1, 2], &[3, 4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3,4\n");
+}
+
+#[test]
+fn interrupt_flushes_buffered_file_output() {
+    let port = free_port();
+    let output = temp_file("interrupt.csv");
+    let child = spawn(port, &["--data-type", "u8", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1], &[2], &[3], &[4], &[5]]);
+    thread::sleep(Duration::from_millis(100));
+
+    let status = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+    let status = child.wait_with_output().unwrap().status;
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "1\n2\n3\n4\n5\n");
+    let _ = fs::remove_file(&output);
+}
+
+#[test]
+fn flush_every_writes_smaller_batches() {
+    let port = free_port();
+    let output = temp_file("flush_every.csv");
+    let child = spawn(port, &["--data-type", "u8", "--flush-every", "2", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1], &[2], &[3]]);
+
+    let content = wait_for_file(&output);
+    stop(child);
+    let _ = fs::remove_file(&output);
+    assert_eq!(content, "1\n2\n");
+}
+
+#[test]
+fn flush_interval_writes_without_full_batch() {
+    let port = free_port();
+    let output = temp_file("flush_interval.csv");
+    let child = spawn(port, &["--data-type", "u8", "--flush-interval", "200ms", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1], &[2]]);
+
+    let content = wait_for_file(&output);
+    stop(child);
+    let _ = fs::remove_file(&output);
+    assert_eq!(content, "1\n2\n");
+}
+
+#[test]
+fn jsonl_writes_arrays_and_objects() {
+    let port = free_port();
+    let child = spawn(port, &["--format", "jsonl", "--data-type", "u8", "--max-packets", "1"]);
+    send(port, &[&[12, 34, 56]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[12,34,56]\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--format", "jsonl", "--data-type", "u8", "--columns", "a,b", "--max-packets", "1"]);
+    send(port, &[&[1, 2]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "{\"a\":1,\"b\":2}\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--format", "jsonl", "--data-type", "bool", "--max-packets", "1"]);
+    send(port, &[&[0b0000_0011]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[true,true,false,false,false,false,false,false]\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--format", "jsonl", "--data-type", "u8", "--filter-max", "100", "--filter-mode", "blank", "--max-packets", "1"]);
+    send(port, &[&[5, 200]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[5,null]\n");
+}
+
+#[test]
+fn source_column_follows_each_sender() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--source-column", "--max-packets", "3"]);
+    let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for (socket, value) in [(&a, 1u8), (&b, 2), (&a, 3)] {
+        socket.send_to(&[value], ("127.0.0.1", port)).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let output = child.wait_with_output().unwrap();
+    let (a, b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{a},1\n{b},2\n{a},3\n"));
+}
+
+#[test]
+fn buffer_size_receives_jumbo_datagrams() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--buffer-size", "1400", "--max-packets", "1"]);
+    send(port, &[&[7; 1400]]);
+
+    let output = child.wait_with_output().unwrap();
+    let row = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(row.trim_end().split(',').count(), 1400);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn truncation_is_reported_once() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--buffer-size", "4", "--max-packets", "3"]);
+    send(port, &[&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6], &[1, 2, 3, 4, 5, 6]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2,3,4\n1,2,3,4\n1,2,3,4\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("truncated").count(), 1);
+    assert!(stderr.contains("datagram of 6 bytes"));
+}
+
+#[test]
+fn stdout_rows_are_newline_terminated_while_capturing() {
+    let port = free_port();
+    let mut child = spawn(port, &["--data-type", "u8"]);
+    send(port, &[&[1, 2], &[3, 4]]);
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut rows = Vec::new();
+    for _ in 0..2 {
+        let mut row = String::new();
+        stdout.read_line(&mut row).unwrap();
+        rows.push(row);
+    }
+    stop(child);
+    assert_eq!(rows, ["1,2\n", "3,4\n"]);
+}
+
+#[test]
+fn schema_decodes_mixed_records() {
+    let port = free_port();
+    let child = spawn(port, &["--schema", "u8,i16,bool", "--format", "jsonl", "--max-packets", "1"]);
+    send(port, &[&[1, 255, 255, 1]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[1,-1,true,false,false,false,false,false,false,false]\n");
+}
+
+#[test]
+fn header_schema_is_decoded_once_before_the_body() {
+    let port = free_port();
+    let child = spawn(port, &["--header-schema", "u8,u16", "--schema", "i8", "--max-packets", "2"]);
+    send(port, &[&[7, 1, 0, 255, 2, 3], &[8, 0]]);
+
+    let output = child.wait_with_output().unwrap();
+    // the second packet ends inside its header
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7,256,-1,2,3\n8\n");
+}
+
+#[test]
+fn nan_and_infinity_are_written_as_tokens() {
+    let packet = [0x7f, 0xc0, 0, 0, 0x7f, 0x80, 0, 0, 0xff, 0x80, 0, 0, 0x3f, 0xc0, 0, 0];
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "f32", "--quote-non-numeric", "--max-packets", "1"]);
+    send(port, &[&packet]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "NaN,inf,-inf,1.5\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "f32", "--float-nan-token", "", "--float-inf-token", "null", "--float-neg-inf-token", "null", "--max-packets", "1"]);
+    send(port, &[&packet]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ",null,null,1.5\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "f32", "--float-inf-token", "Infinity", "--format", "jsonl", "--max-packets", "1"]);
+    send(port, &[&packet]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "[null,null,null,1.5]\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "f32", "--float-inf-token", "Infinity", "--max-packets", "1"]);
+    send(port, &[&packet]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "NaN,Infinity,-Infinity,1.5\n");
+}
+
+#[test]
+fn gzip_output_is_finished_on_shutdown() {
+    let port = free_port();
+    let output = temp_file("capture.csv.gz");
+    let child = spawn(port, &["--data-type", "u8", "--max-packets", "2", "--compress", "gzip", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1, 2], &[3, 4]]);
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let mut content = String::new();
+    GzDecoder::new(fs::File::open(&output).unwrap()).read_to_string(&mut content).unwrap();
+    let _ = fs::remove_file(&output);
+    assert_eq!(content, "1,2\n3,4\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--compress", "gzip"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--output"));
+}
+
+#[test]
+fn existing_output_is_truncated_unless_appending() {
+    let output = temp_file("existing.csv");
+    for (args, expected) in [(&[][..], "1\n"), (&["--append"][..], "1\n1\n")] {
+        let port = free_port();
+        let child = spawn(port, &[&["--data-type", "u8", "--max-packets", "1", "--output", output.to_str().unwrap()], args].concat());
+        send(port, &[&[1]]);
+        assert!(child.wait_with_output().unwrap().status.success());
+        assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+    }
+    let _ = fs::remove_file(&output);
+}
+
+#[test]
+fn appending_writes_the_header_only_once() {
+    let output = temp_file("appended.csv");
+    for _ in 0..2 {
+        let port = free_port();
+        let child = spawn(port, &["--data-type", "u8", "--header", "--append", "--max-packets", "1", "--output", output.to_str().unwrap()]);
+        send(port, &[&[1, 2]]);
+        assert!(child.wait_with_output().unwrap().status.success());
+    }
+    assert_eq!(fs::read_to_string(&output).unwrap(), "col0,col1\n1,2\n1,2\n");
+    let _ = fs::remove_file(&output);
+}
+
+#[test]
+fn convert_decodes_a_file_in_rows() {
+    let input = temp_file("dump.bin");
+    fs::write(&input, [0, 1, 0, 2, 0, 3, 0, 4, 0, 5]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["convert", "--in", input.to_str().unwrap(), "--values-per-row", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3,4\n5\n");
+
+    let csv = temp_file("dump.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--header", "convert", "--in", input.to_str().unwrap(), "--schema", "u8,u8", "--output", csv.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&csv).unwrap(), "col0,col1\n0,1\n0,2\n0,3\n0,4\n0,5\n");
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&csv);
+}
+
+#[test]
+fn unwritable_output_is_reported_at_startup() {
+    let output = std::env::temp_dir().join("udp_to_csv_missing_dir").join("out.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--output", output.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Could not open output file"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn write_error_ends_capture_with_message() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--flush-every", "1", "--output", "/dev/full"]);
+    send(port, &[&[1]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Could not write to output file /dev/full"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn async_mode_writes_rows() {
+    let port = free_port();
+    let child = spawn(port, &["--async", "--data-type", "u8", "--max-packets", "2"]);
+    send(port, &[&[1, 2], &[3, 4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3,4\n");
+}
+
+#[test]
+fn list_interfaces_needs_no_bind_address() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv")).arg("--list-interfaces").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("127.0.0.1"));
+}
+
+#[test]
+fn bind_defaults_to_all_interfaces() {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--port", &port.to_string(), "--data-type", "u8", "--max-packets", "1"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    send(port, &[&[9]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "9\n");
+}
+
+#[test]
+fn max_file_size_rotates_with_header() {
+    let port = free_port();
+    let output = temp_file("rotate.csv");
+    let rotated: Vec<PathBuf> = (1..=3).map(|i| temp_file(&format!("rotate.{i}.csv"))).collect();
+    let child = spawn(port, &[
+        "--data-type", "u8", "--header", "--flush-every", "1", "--max-file-size", "6",
+        "--max-packets", "3", "--output", output.to_str().unwrap(),
+    ]);
+    send(port, &[&[1, 2], &[3, 4], &[5, 6]]);
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    assert_eq!(fs::read_to_string(&output).unwrap(), "col0,col1\n1,2\n");
+    assert_eq!(fs::read_to_string(&rotated[0]).unwrap(), "col0,col1\n3,4\n");
+    assert_eq!(fs::read_to_string(&rotated[1]).unwrap(), "col0,col1\n5,6\n");
+    assert!(!rotated[2].exists());
+    for path in rotated.iter().chain([&output]) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn rotate_interval_names_files_after_period_start() {
+    let port = free_port();
+    let output = temp_file("timed.csv");
+    let prefix = output.file_stem().unwrap().to_str().unwrap().to_owned() + "-";
+    let child = spawn(port, &[
+        "--data-type", "u8", "--rotate-interval", "1s", "--max-packets", "2", "--output", output.to_str().unwrap(),
+    ]);
+    // both rows are flushed in one batch at the end, each still goes to the file of its period
+    send(port, &[&[1]]);
+    thread::sleep(Duration::from_millis(1100));
+    send(port, &[&[2]]);
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let mut files: Vec<PathBuf> = fs::read_dir(std::env::temp_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap().to_str().unwrap().starts_with(&prefix))
+        .collect();
+    files.sort();
+    let mut contents: Vec<String> = files.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+    for path in &files {
+        let _ = fs::remove_file(path);
+    }
+    assert!(!output.exists());
+    // the file opened at startup stays empty if its period ended before the first packet
+    contents.retain(|content| !content.is_empty());
+    assert_eq!(contents, ["1\n", "2\n"]);
+    // e.g. -2024-06-01T130501.csv
+    let name = files[0].file_name().unwrap().to_str().unwrap();
+    assert_eq!(name.len(), prefix.len() + "2024-06-01T130501.csv".len());
+}
+
+#[test]
+fn index_column_counts_packets() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--index-column", "--header", "--max-packets", "3"]);
+    send(port, &[&[7], &[8], &[9]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "index,col0\n0,7\n1,8\n2,9\n");
+}
+
+#[test]
+fn options_from_environment_with_cli_precedence() {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .env("UDP_TO_CSV_PORT", port.to_string())
+        .env("UDP_TO_CSV_DATA_TYPE", "u16")
+        .env("UDP_TO_CSV_MAX_PACKETS", "1")
+        .args(["--data-type", "u8"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    send(port, &[&[1, 2]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n");
+}
+
+#[test]
+fn sample_reports_on_stderr_and_leaves_output_alone() {
+    let port = free_port();
+    let output = temp_file("sample.csv");
+    fs::write(&output, "previous\n").unwrap();
+    let child = spawn(port, &["--sample", "2", "--output", output.to_str().unwrap()]);
+    send(port, &[&[0, 1, 0, 2], &[0, 3, 4]]);
+
+    let result = child.wait_with_output().unwrap();
+    assert!(result.status.success());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "previous\n");
+    let _ = fs::remove_file(&output);
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("4 bytes, 2 values: 1,2"), "{stderr}");
+    assert!(stderr.contains("3 bytes, 1 values: 3"), "{stderr}");
+    assert!(result.stdout.is_empty());
+}
+
+#[test]
+fn unresolvable_bind_fails() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "no-such-host.invalid", "--port", &free_port().to_string()])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Could not resolve no-such-host.invalid"), "{stderr}");
+}
+
+#[test]
+fn quiet_only_logs_errors() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--quiet", "--bind", "203.0.113.1", "--port", &free_port().to_string()])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Could not bind"), "{stderr}");
+    assert!(!stderr.contains("network interfaces"), "{stderr}");
+}
+
+#[test]
+fn bind_failure_keeps_stdout_clean() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "203.0.113.1", "--port", &free_port().to_string()])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Available network interfaces"), "{stderr}");
+    assert!(stderr.contains("127.0.0.1"), "{stderr}");
+}
+
+#[test]
+fn retry_bind_waits_for_the_port() {
+    let port = free_port();
+    let holder = UdpSocket::bind(("127.0.0.1", port)).unwrap();
+    let child = spawn(port, &["--retry-bind", "100ms", "--max-packets", "1"]);
+    drop(holder);
+    // the third retry comes 400ms after the second
+    thread::sleep(Duration::from_millis(700));
+    send(port, &[&[0, 7]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("retrying in 100ms"), "{stderr}");
+}
+
+#[test]
+fn retry_bind_max_gives_up() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "203.0.113.1", "--port", &free_port().to_string(), "--retry-bind", "10ms", "--retry-bind-max", "2"])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("retrying in").count(), 2, "{stderr}");
+    assert!(stderr.contains("retrying in 20ms"), "{stderr}");
+    assert!(stderr.contains("Available network interfaces"), "{stderr}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn long_first_row_does_not_reserve_a_batch_of_them() {
+    let peak = |pid: u32| -> u64 {
+        let status = fs::read_to_string(format!("/proc/{pid}/status")).unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmPeak:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    };
+    let port = free_port();
+    let output = temp_file("long_row.csv");
+    let child = spawn(port, &["--data-type", "hex", "--buffer-size", "65536", "--output", output.to_str().unwrap()]);
+    let before = peak(child.id());
+    send(port, &[&[0; 60_000]]);
+    thread::sleep(Duration::from_millis(200));
+    let grown = peak(child.id()) - before;
+    stop(child);
+    let _ = fs::remove_file(&output);
+    // a row of 120 kB times the default --flush-every would be 120 MB
+    assert!(grown < 32 * 1024, "grew by {grown} kB");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kernel_drops_are_reported() {
+    let port = free_port();
+    let child = spawn(port, &["--rcvbuf", "4096", "--duration", "2s", "--progress-interval", "1500ms"]);
+    // a stopped process doesn't read, so the small receive buffer overflows
+    let pid = child.id().to_string();
+    assert!(Command::new("kill").args(["-STOP", &pid]).status().unwrap().success());
+    send(port, &[&[0u8; 512] as &[u8]; 200]);
+    assert!(Command::new("kill").args(["-CONT", &pid]).status().unwrap().success());
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("dropped by the kernel"), "{stderr}");
+    assert!(stderr.contains("the kernel dropped"), "{stderr}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kernel_timestamp_is_taken_on_arrival() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--timestamp", "unix_ms", "--kernel-timestamp", "--max-packets", "1"]);
+    // the packet waits in the socket while the process is stopped, the kernel stamped it already
+    let pid = child.id().to_string();
+    assert!(Command::new("kill").args(["-STOP", &pid]).status().unwrap().success());
+    let sent = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+    send(port, &[&[1]]);
+    thread::sleep(Duration::from_millis(500));
+    assert!(Command::new("kill").args(["-CONT", &pid]).status().unwrap().success());
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (time, value) = stdout.trim_end().split_once(',').unwrap();
+    let time: u128 = time.parse().unwrap();
+    assert_eq!(value, "1");
+    assert!(time >= sent && time < sent + 250, "sent at {sent}, stamped {time}");
+}
+
+#[test]
+fn monotonic_timestamps_count_nanoseconds_since_start() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--timestamp", "monotonic_ns", "--max-packets", "2"]);
+    send(port, &[&[1]]);
+    thread::sleep(Duration::from_millis(50));
+    send(port, &[&[2]]);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let times: Vec<u64> = stdout.lines().map(|line| line.split_once(',').unwrap().0.parse().unwrap()).collect();
+    assert!(times[0] > 0, "{stdout}");
+    assert!(times[1] - times[0] >= 50_000_000, "{stdout}");
+}
+
+#[test]
+fn non_numeric_fields_are_quoted() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--source-column", "--quote-non-numeric", "--max-packets", "1"]);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(&[1], ("127.0.0.1", port)).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("\"{}\",1\n", socket.local_addr().unwrap()));
+
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--header", "--columns", "a \"b\",c:d", "--delimiter", ":", "--max-packets", "1"]);
+    send(port, &[&[1, 2]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "\"a \"\"b\"\"\":\"c:d\"\n1:2\n");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn binds_to_named_interface() {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--interface", "lo", "--port", &port.to_string(), "--data-type", "u8", "--max-packets", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    send(port, &[&[7]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n");
+}
+
+#[test]
+fn unknown_interface_lists_available_ones() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--interface", "nonexistent0", "--port", &free_port().to_string()])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No IPv4 address found for interface nonexistent0"), "{stderr}");
+    assert!(stderr.contains("Available network interfaces"), "{stderr}");
+}
+
+#[test]
+fn multicast_zone_must_name_an_interface() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--port", &free_port().to_string(), "--multicast-group", "ff02::1%nonexistent0"])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Could not find interface nonexistent0"), "{stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--port", &free_port().to_string(), "--multicast-group", "239.1.2.3%eth0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("only IPv6 groups take a zone"), "{stderr}");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn reuse_lets_two_captures_share_a_port() {
+    let port = free_port();
+    let mut first = spawn(port, &["--reuse-address", "--reuse-port"]);
+    let mut second = spawn(port, &["--reuse-address", "--reuse-port"]);
+
+    let first_running = first.try_wait().unwrap().is_none();
+    let second_running = second.try_wait().unwrap().is_none();
+    stop(first);
+    stop(second);
+    assert!(first_running);
+    assert!(second_running);
+}
+
+#[test]
+fn rcvbuf_reports_granted_size() {
+    let port = free_port();
+    let child = spawn(port, &["--rcvbuf", "65536", "--data-type", "u8", "--max-packets", "1"]);
+    send(port, &[&[1]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("receive buffer is"), "{stderr}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+}
+
+#[test]
+fn ascii_payload_is_one_text_field() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "ascii", "--max-packets", "2"]);
+    send(port, &[b"$GPGLL,4916.45,N", b"plain"]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "\"$GPGLL,4916.45,N\"\nplain\n");
+}
+
+#[test]
+fn out_of_range_rows_are_dropped() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--filter-max", "100", "--max-packets", "3"]);
+    send(port, &[&[1, 2], &[3, 250], &[4, 5]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n4,5\n");
+}
+
+#[test]
+fn idle_timeout_warns_and_can_stop_the_capture() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--idle-timeout", "300ms", "--exit-on-idle"]);
+    send(port, &[&[1]]);
+
+    let start = Instant::now();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("no data for"), "{stderr}");
+}
+
+#[test]
+fn summary_is_logged_on_exit() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u16", "--max-packets", "2"]);
+    send(port, &[&[0, 1, 0, 2], &[0, 3]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("captured 2 packets with 3 values, 6 bytes (3.0 bytes per packet)"), "{stderr}");
+}
+
+#[test]
+fn sample_every_keeps_every_nth_packet() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--sample-every", "3", "--max-packets", "3"]);
+    send(port, &[&[1], &[2], &[3], &[4], &[5], &[6], &[7]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n4\n7\n");
+}
+
+#[test]
+fn several_ports_feed_one_output() {
+    let (first, second) = (free_port(), free_port());
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &format!("{first},{second}"), "--port-column", "--data-type", "u8", "--max-packets", "2"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    send(first, &[&[1]]);
+    thread::sleep(Duration::from_millis(50));
+    send(second, &[&[2]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{first},1\n{second},2\n"));
+}
+
+#[test]
+fn auto_endianness_decodes_held_back_packets() {
+    let port = free_port();
+    let child = spawn(port, &["--endianness", "auto", "--max-packets", "3"]);
+    send(port, &[&[1, 0], &[2, 0], &[3, 0]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n3\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("guessed little endian"), "{stderr}");
+}
+
+#[test]
+fn transpose_writes_columns_as_rows() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--header", "--transpose", "--max-packets", "3"]);
+    send(port, &[&[1, 2], &[3, 4], &[5, 6]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "col0,1,3,5\ncol1,2,4,6\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--transpose"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("needs --max-packets or --duration"), "{stderr}");
+}
+
+#[test]
+fn fsync_still_writes_every_batch() {
+    let port = free_port();
+    let output = temp_file("fsync.csv");
+    let child = spawn(port, &["--data-type", "u8", "--fsync", "--flush-every", "1", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1, 2]]);
+
+    let content = wait_for_file(&output);
+    stop(child);
+    let _ = fs::remove_file(&output);
+    assert_eq!(content, "1,2\n");
+}
+
+#[test]
+fn workers_keep_rows_in_arrival_order() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u16", "--workers", "4", "--max-packets", "200"]);
+    let packets: Vec<[u8; 2]> = (0..200u16).map(u16::to_be_bytes).collect();
+    let packets: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+    send(port, &packets);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let expected: String = (0..200).map(|i| format!("{i}\n")).collect();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn trigger_writes_packets_around_the_event() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--trigger-threshold", "100", "--pretrigger", "2", "--posttrigger", "1", "--max-packets", "8"]);
+    send(port, &[&[1], &[2], &[3], &[4], &[200], &[5], &[6], &[7]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n4\n200\n5\n");
+}
+
+#[test]
+fn length_framing_splits_datagrams_into_rows() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--framing", "u16-length", "--max-packets", "3"]);
+    send(port, &[&[0, 2, 1, 2, 0, 1, 3], &[0, 1, 4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3\n4\n");
+}
+
+#[test]
+fn sync_word_aligns_rows_and_reports_discarded_bytes() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--sync-word", "0xAA55", "--max-packets", "3"]);
+    send(port, &[&[9, 9, 0xaa, 0x55, 1, 2, 0xaa, 0x55, 3], &[7, 0xaa, 0x55, 4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3\n4\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("discarded 3 bytes"));
+}
+
+#[test]
+fn metadata_comments_precede_the_header() {
+    let port = free_port();
+    let child = spawn(port, &["--schema", "u8,i16", "--endianness", "little", "--delimiter", ";", "--metadata", "--header", "--max-packets", "1"]);
+    send(port, &[&[1, 2, 0]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].starts_with("# udp_to_csv "));
+    assert!(lines[1].starts_with("# started: ") && lines[1].ends_with('Z'));
+    assert_eq!(lines[2..], [
+        "# transport: udp", "# bind: 127.0.0.1", &format!("# port: {port}"), "# schema: u8,i16", "# endianness: little", "# delimiter: ;",
+        "col0;col1", "1;2",
+    ]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--metadata", "--format", "jsonl"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--metadata writes # comment lines"));
+}
+
+#[test]
+fn checksum_drops_corrupt_packets_and_strips_the_crc() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--checksum", "crc16", "--duration", "1s"]);
+    send(port, &[&[1, 2, 0x0e, 0x7c], &[3, 0xd1, 0x94], &[3, 0xd1, 0x93], &[1]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("rejected 2 packets with a wrong crc16 checksum"));
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_output_stays_open_and_survives_the_reader_leaving() {
+    let fifo = temp_file("fifo");
+    assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--flush-every", "1", "--max-packets", "4", "--output", fifo.to_str().unwrap()]);
+    let mut reader = BufReader::new(fs::File::open(&fifo).unwrap());
+    send(port, &[&[1], &[2]]);
+    let mut rows = String::new();
+    reader.read_line(&mut rows).unwrap();
+    reader.read_line(&mut rows).unwrap();
+    assert_eq!(rows, "1\n2\n");
+    drop(reader);
+    send(port, &[&[3], &[4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("dropping rows"));
+    fs::remove_file(&fifo).unwrap();
+}
+
+#[test]
+fn bind_accepts_a_hostname() {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "localhost", "--port", &port.to_string(), "--data-type", "u8", "--max-packets", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    send(port, &[&[7]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n");
+}
+
+#[test]
+fn tee_prints_rows_while_the_file_is_batched() {
+    let output = temp_file("tee.csv");
+    let port = free_port();
+    let mut child = spawn(port, &["--data-type", "u8", "--header", "--tee", "--output", output.to_str().unwrap(), "--max-packets", "2"]);
+    send(port, &[&[1, 2]]);
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut rows = String::new();
+    stdout.read_line(&mut rows).unwrap();
+    stdout.read_line(&mut rows).unwrap();
+    assert_eq!(rows, "col0,col1\n1,2\n");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "");
+
+    send(port, &[&[3]]);
+    assert!(child.wait().unwrap().success());
+    stdout.read_to_string(&mut rows).unwrap();
+    assert_eq!(rows, "col0,col1\n1,2\n3\n");
+    assert_eq!(fs::read_to_string(&output).unwrap(), rows);
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn select_columns_picks_and_orders_values() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--select-columns", "2,0,5", "--header", "--max-packets", "1"]);
+    send(port, &[&[10, 11, 12]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "col2,col0,col5\n12,10,\n");
+}
+
+#[test]
+fn progress_is_logged_while_idle() {
+    let output = temp_file("progress.csv");
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--progress-interval", "200ms", "--duration", "1s", "--flush-every", "1", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1, 2]]);
+
+    let result = child.wait_with_output().unwrap();
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains(&format!("1 packets received, 1 rows written, {} is 4 bytes", output.display())), "{stderr}");
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn config_file_fills_in_options_not_given_on_the_command_line() {
+    let config = temp_file("config.toml");
+    fs::write(&config, "data_type = \"u8\"\ndelimiter = \";\"\nheader = true\nmax-packets = 5\n").unwrap();
+    let port = free_port();
+    let child = spawn(port, &["--config", config.to_str().unwrap(), "--max-packets", "1"]);
+    send(port, &[&[1, 2]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "col0;col1\n1;2\n");
+
+    fs::write(&config, "data-typ = \"u8\"\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv")).args(["--config", config.to_str().unwrap()]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("unknown option `data-typ`"));
+    fs::remove_file(&config).unwrap();
+}
+
+#[test]
+fn many_values_in_the_first_packet_are_reported() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "bool", "--max-packets", "1"]);
+    send(port, &[&[0; 9]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("the first packet decoded to 72 values, check --data-type or --schema, bool writes 8 columns per byte"));
+}
+
+#[test]
+fn once_writes_the_first_packet_and_exits() {
+    let output = temp_file("once.csv");
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--once", "--output", output.to_str().unwrap()]);
+    send(port, &[&[1, 2], &[3]]);
+
+    assert!(child.wait_with_output().unwrap().status.success());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "1,2\n");
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn input_replays_recorded_packets() {
+    let input = temp_file("input.bin");
+    fs::write(&input, [0, 0, 0, 2, 1, 2, 0, 0, 0, 0, 0, 0, 0, 1, 3]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--input", input.to_str().unwrap(), "--data-type", "u8", "--port-column"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0,1,2\n0\n0,3\n");
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn missing_input_fails() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--input", temp_file("missing.bin").to_str().unwrap(), "--data-type", "u8"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Could not open input file"));
+}
+
+#[test]
+fn input_rejects_an_oversized_record() {
+    let input = temp_file("oversized.bin");
+    fs::write(&input, [0, 0, 0, 1, 7, 0xff, 0xff, 0xff, 0xff, 1, 2]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--input", input.to_str().unwrap(), "--data-type", "u8"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("input record of 4294967295 bytes exceeds"), "{stderr}");
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn replay_realtime_keeps_the_recorded_gaps() {
+    let input = temp_file("realtime.bin");
+    let mut recording = Vec::new();
+    for (micros, value) in [(5_000_000u64, 1u8), (5_400_000, 2), (5_800_000, 3)] {
+        recording.extend(micros.to_be_bytes());
+        recording.extend([0, 0, 0, 1, value]);
+    }
+    fs::write(&input, recording).unwrap();
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--input", input.to_str().unwrap(), "--data-type", "u8", "--replay-realtime"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n3\n");
+    assert!(start.elapsed() >= Duration::from_millis(800));
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn replay_rate_paces_packets() {
+    let input = temp_file("rate.bin");
+    fs::write(&input, [0, 0, 0, 1, 1, 0, 0, 0, 1, 2, 0, 0, 0, 1, 3]).unwrap();
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--input", input.to_str().unwrap(), "--data-type", "u8", "--replay-rate", "5"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n3\n");
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn full_channel_drops_packets_instead_of_growing() {
+    let port = free_port();
+    // nobody reads stdout until the capture ends, so the writer stalls once the pipe is full
+    let child = spawn(port, &["--data-type", "u8", "--channel-capacity", "1", "--duration", "2s"]);
+    let packet = [0u8; 64];
+    send(port, &vec![&packet[..]; 2000]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("see --channel-capacity"), "{stderr}");
+    assert!(stderr.contains("because the writer fell behind"), "{stderr}");
+}
+
+#[test]
+fn crlf_line_ending_terminates_every_row() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--header", "--line-ending", "crlf", "--max-packets", "2"]);
+    send(port, &[&[1, 2], &[3, 4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "col0,col1\r\n1,2\r\n3,4\r\n");
+}
+
+#[test]
+fn count_column_follows_the_values() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u16", "--header", "--count-column", "--max-packets", "2"]);
+    send(port, &[&[0, 1, 0, 2], &[0, 3]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "col0,col1,count\n1,2,2\n3,1\n");
+
+    let port = free_port();
+    let child = spawn(port, &["--format", "jsonl", "--data-type", "u8", "--columns", "a", "--count-column", "--max-packets", "1"]);
+    send(port, &[&[7]]);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "{\"a\":7,\"count\":1}\n");
+}
+
+#[test]
+fn null_value_fills_filtered_and_missing_fields() {
+    let port = free_port();
+    let child = spawn(port, &[
+        "--schema", "u8,u8,u8", "--filter-max", "100", "--filter-mode", "blank", "--null-value", "NaN", "--max-packets", "2",
+    ]);
+    send(port, &[&[1, 200, 3], &[4]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,NaN,3\n4,NaN,NaN\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn uds_transport_receives_datagrams_and_removes_the_socket() {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = temp_file("capture.sock");
+    let child = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--transport", "uds", "--path", path.to_str().unwrap(), "--data-type", "u8", "--max-packets", "2"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let socket = UnixDatagram::unbound().unwrap();
+    socket.send_to(&[1, 2], &path).unwrap();
+    socket.send_to(&[3], &path).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n3\n");
+    assert!(!path.exists());
+}
+
+#[test]
+fn max_values_per_packet_cuts_off_long_rows() {
+    let port = free_port();
+    let child = spawn(port, &["--data-type", "u8", "--max-values-per-packet", "2", "--max-packets", "2"]);
+    send(port, &[&[1, 2, 3, 4], &[5]]);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,2\n5\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("decodes to 4 values, only the first 2"));
+}
+
+#[test]
+fn parquet_without_output_fails() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--format", "parquet"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--format parquet needs"), "{stderr}");
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_writes_typed_columns() {
+    use arrow_array::{Array, Int16Array, UInt8Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let output = temp_file("out.parquet");
+    let port = free_port();
+    let child = spawn(port, &[
+        "--schema", "u8,i16", "--format", "parquet", "--output", output.to_str().unwrap(), "--flush-every", "1", "--max-packets", "2",
+    ]);
+    send(port, &[&[1, 255, 254], &[2]]);
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&output).unwrap()).unwrap().build().unwrap();
+    let batch = reader.map(Result::unwrap).next().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    let first = batch.column(0).as_any().downcast_ref::<UInt8Array>().unwrap();
+    let second = batch.column(1).as_any().downcast_ref::<Int16Array>().unwrap();
+    assert_eq!((first.value(0), second.value(0)), (1, -2));
+    assert_eq!(first.value(1), 2);
+    assert!(second.is_null(1));
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn msgpack_rejects_tee() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--format", "msgpack", "--tee", "--output", "out.msgpack"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--format msgpack"), "{stderr}");
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_writes_length_prefixed_arrays() {
+    let port = free_port();
+    let child = spawn(port, &["--schema", "u8,i16", "--index-column", "--format", "msgpack", "--filter-max", "100", "--filter-mode", "blank", "--max-packets", "2"]);
+    send(port, &[&[1, 255, 254], &[200, 0, 5]]);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    // fixarray of 3, positive and negative fixints, nil for the filtered value
+    assert_eq!(output.stdout, [0, 0, 0, 4, 0x93, 0, 1, 0xfe, 0, 0, 0, 4, 0x93, 1, 0xc0, 5]);
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[test]
+fn sqlite_needs_the_feature() {
+    let output = Command::new(env!("CARGO_BIN_EXE_udp_to_csv"))
+        .args(["--bind", "127.0.0.1", "--port", &free_port().to_string(), "--output-sqlite", "out.db"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("needs udp_to_csv built with the sqlite feature"), "{stderr}");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_inserts_rows_into_table() {
+    let output = temp_file("out.db");
+    let port = free_port();
+    let child = spawn(port, &[
+        "--schema", "u8,i16", "--columns", "id,level", "--index-column", "--output-sqlite", output.to_str().unwrap(),
+        "--table", "readings", "--flush-every", "1", "--max-packets", "2",
+    ]);
+    send(port, &[&[1, 255, 254], &[2]]);
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let connection = rusqlite::Connection::open(&output).unwrap();
+    let mut select = connection.prepare("SELECT \"index\", id, level FROM readings ORDER BY \"index\"").unwrap();
+    let rows: Vec<(i64, i64, Option<i64>)> = select.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(rows, [(0, 1, Some(-2)), (1, 2, None)]);
+    drop(select);
+    drop(connection);
+    fs::remove_file(&output).unwrap();
+}
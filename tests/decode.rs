@@ -0,0 +1,279 @@
+use udp_to_csv::{crc16, crc32, decode_packet, decode_row, detect_endianness, trailing_bytes, value_count, BitOrder, Checksum, CsvRows, CsvTokens, DataType, DecodeError, DecodeOptions, Deframer, Endianness, FilterMode, Framing, RecvErrors, Value, MAX_RECV_ERROR_INTERVAL, RECV_ERROR_INTERVAL};
+
+fn decode(bytes: &[u8], data_type: DataType) -> Vec<String> {
+    text(decode_packet(bytes, &[data_type], &DecodeOptions::default()))
+}
+
+fn text(values: Result<Vec<Value>, DecodeError>) -> Vec<String> {
+    values.expect("packet should decode").iter().map(Value::to_string).collect()
+}
+
+#[test]
+fn bool_expands_bits_lsb_first() {
+    assert_eq!(decode(&[0b0000_0101], DataType::Bool), ["1", "0", "1", "0", "0", "0", "0", "0"]);
+}
+
+#[test]
+fn unsigned_integers() {
+    assert_eq!(decode(&[1, 255], DataType::U8), ["1", "255"]);
+    assert_eq!(decode(&[1, 2, 255, 255], DataType::U16), ["258", "65535"]);
+    assert_eq!(decode(&[0, 0, 1, 0], DataType::U32), ["256"]);
+    assert_eq!(decode(&[255; 8], DataType::U64), ["18446744073709551615"]);
+}
+
+#[test]
+fn signed_integers() {
+    assert_eq!(decode(&[255, 127], DataType::I8), ["-1", "127"]);
+    assert_eq!(decode(&[255, 254], DataType::I16), ["-2"]);
+    assert_eq!(decode(&[128, 0, 0, 0], DataType::I32), ["-2147483648"]);
+    assert_eq!(decode(&[255; 8], DataType::I64), ["-1"]);
+}
+
+#[test]
+fn floats() {
+    assert_eq!(decode(&1.5f32.to_be_bytes(), DataType::F32), ["1.5"]);
+    assert_eq!(decode(&(-0.1f64).to_be_bytes(), DataType::F64), ["-0.1"]);
+}
+
+#[test]
+fn canonical_nan_and_infinities() {
+    assert_eq!(decode(&[0x7f, 0xc0, 0, 0, 0x7f, 0x80, 0, 0, 0xff, 0x80, 0, 0], DataType::F32), ["NaN", "inf", "-inf"]);
+    let f64_bits = [0x7ff8_0000_0000_0000u64, 0x7ff0_0000_0000_0000, 0xfff0_0000_0000_0000];
+    let bytes: Vec<u8> = f64_bits.iter().flat_map(|bits| bits.to_be_bytes()).collect();
+    assert_eq!(decode(&bytes, DataType::F64), ["NaN", "inf", "-inf"]);
+}
+
+#[test]
+fn csv_rows_write_tokens_and_empty_rows() {
+    let tokens = CsvTokens { null: "-".to_owned(), nan: String::new(), ..CsvTokens::default() };
+    let mut builder = csv::WriterBuilder::new();
+    builder.flexible(true);
+    let mut rows = CsvRows::new(builder, "\n", tokens);
+    let mut out = String::new();
+    let values = [Value::U8(1), Value::Filtered, Value::F64(f64::NAN), Value::F32(f32::NEG_INFINITY), Value::Text("a,b".to_owned())];
+    rows.write_row(["7"], &values, [], &mut out);
+    rows.write_row([], &[], [], &mut out);
+    rows.write_row([], &[], ["3"], &mut out);
+    assert_eq!(out, "7,1,-,,-inf,\"a,b\"\n\n3\n");
+}
+
+#[test]
+fn little_endian() {
+    let options = DecodeOptions { endianness: Endianness::Little, ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[1, 2], &[DataType::U16], &options)), ["513"]);
+    assert_eq!(text(decode_packet(&[1, 2], &[DataType::U8], &options)), ["1", "2"]);
+}
+
+#[test]
+fn three_byte_integers() {
+    assert_eq!(decode(&[0, 1, 0, 255, 255, 255], DataType::U24), ["256", "16777215"]);
+    assert_eq!(decode(&[255, 255, 254, 128, 0, 0, 127, 255, 255], DataType::I24), ["-2", "-8388608", "8388607"]);
+    let options = DecodeOptions { endianness: Endianness::Little, ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[254, 255, 255], &[DataType::I24], &options)), ["-2"]);
+    assert_eq!(trailing_bytes(7, &[DataType::U24]), 1);
+}
+
+#[test]
+fn bool_bits_and_order() {
+    let lsb = DecodeOptions { bool_bits: 4, ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[0b1111_0001], &[DataType::Bool], &lsb)), ["1", "0", "0", "0"]);
+    let msb = DecodeOptions { bool_bits: 4, bool_order: BitOrder::Msb, ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[0b1111_0001], &[DataType::Bool], &msb)), ["0", "0", "0", "1"]);
+}
+
+#[test]
+fn bool_order_does_not_follow_endianness() {
+    let byte = [0b1000_0001, 0b0000_0010];
+    for endianness in [Endianness::Big, Endianness::Little] {
+        let lsb = DecodeOptions { endianness, ..DecodeOptions::default() };
+        assert_eq!(text(decode_packet(&byte, &[DataType::Bool], &lsb)), ["1", "0", "0", "0", "0", "0", "0", "1", "0", "1", "0", "0", "0", "0", "0", "0"]);
+        let msb = DecodeOptions { bool_order: BitOrder::Msb, ..lsb };
+        assert_eq!(text(decode_packet(&byte, &[DataType::Bool], &msb)), ["1", "0", "0", "0", "0", "0", "0", "1", "0", "0", "0", "0", "0", "0", "1", "0"]);
+    }
+}
+
+#[test]
+fn trailing_partial_value_is_an_error() {
+    let options = DecodeOptions::default();
+    let truncated = decode_packet(&[0, 0, 0, 1, 0, 0], &[DataType::U32], &options);
+    assert!(matches!(truncated, Err(DecodeError::Truncated { expected: 4, got: 2 })));
+    assert_eq!(decode_row(&[0, 1, 0], &[DataType::U16], &options), Some(vec![Value::U16(1)]));
+    assert!(decode(&[], DataType::U8).is_empty());
+}
+
+#[test]
+fn schema_repeats_until_packet_is_exhausted() {
+    let schema = [DataType::U8, DataType::I16, DataType::I16, DataType::U32];
+    let record = [3, 255, 254, 0, 5, 0, 0, 1, 0];
+    let packet: Vec<u8> = record.iter().chain(&record).chain(&[4]).copied().collect();
+    assert_eq!(
+        text(decode_packet(&packet, &schema, &DecodeOptions::default())),
+        ["3", "-2", "5", "256", "3", "-2", "5", "256", "4"],
+    );
+}
+
+#[test]
+fn scale_and_offset_apply_to_numbers_only() {
+    let options = DecodeOptions { scale: Some(0.00125), offset: Some(-1.0), ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[3, 232], &[DataType::U16], &options)), ["0.25"]);
+    assert_eq!(text(decode_packet(&[1], &[DataType::Bool], &DecodeOptions { bool_bits: 2, ..options })), ["1", "0"]);
+    let offset = DecodeOptions { offset: Some(0.5), ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[255], &[DataType::I8], &offset)), ["-0.5"]);
+}
+
+#[test]
+fn trailing_bytes_left_after_last_value() {
+    assert_eq!(trailing_bytes(5, &[DataType::U16]), 1);
+    assert_eq!(trailing_bytes(8, &[DataType::U32]), 0);
+    assert_eq!(trailing_bytes(7, &[DataType::U64]), 7);
+    // one record of 3 bytes, a u8 and a single byte of the next i16
+    assert_eq!(trailing_bytes(5, &[DataType::U8, DataType::I16]), 1);
+}
+
+#[test]
+fn hex_takes_rest_of_packet() {
+    assert_eq!(decode(&[0x0a, 0x1b, 0x2c], DataType::Hex), ["0a1b2c"]);
+    let spaced = DecodeOptions { hex_spaced: true, ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[0x0a, 0x1b, 0x2c], &[DataType::U8, DataType::Hex], &spaced)), ["10", "1b 2c"]);
+    assert!(decode(&[], DataType::Hex).is_empty());
+    assert_eq!(trailing_bytes(3, &[DataType::U8, DataType::Hex]), 0);
+    assert_eq!(trailing_bytes(1, &[DataType::U16, DataType::Hex]), 1);
+}
+
+#[test]
+fn ascii_keeps_text_and_replaces_invalid_utf8() {
+    assert_eq!(decode(b"$GPGGA*47", DataType::Ascii), ["$GPGGA*47"]);
+    assert_eq!(decode(&[b'a', 0xff, b'b'], DataType::Ascii), ["a\u{fffd}b"]);
+}
+
+#[test]
+fn filter_drops_or_blanks_out_of_range_values() {
+    let drop = DecodeOptions { filter_min: Some(2.0), filter_max: Some(200.0), ..DecodeOptions::default() };
+    assert_eq!(decode_row(&[2, 100, 200], &[DataType::U8], &drop), Some(vec![Value::U8(2), Value::U8(100), Value::U8(200)]));
+    assert_eq!(decode_row(&[1, 100], &[DataType::U8], &drop), None);
+    let blank = DecodeOptions { filter_mode: FilterMode::Blank, ..drop };
+    assert_eq!(decode_row(&[1, 100, 255], &[DataType::U8], &blank), Some(vec![Value::Filtered, Value::U8(100), Value::Filtered]));
+    assert_eq!(text(decode_packet(&[1, 100], &[DataType::U8], &drop)), ["", "100"]);
+    // bools are not numeric readings
+    assert_eq!(decode_row(&[1], &[DataType::Bool], &drop).map(|values| values.len()), Some(8));
+}
+
+#[test]
+fn fixed_point_divides_integers_before_scaling() {
+    let q15 = DecodeOptions { fixed_point: Some(15), ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[0x40, 0x00, 0x80, 0x00], &[DataType::I16], &q15)), ["0.5", "-1"]);
+    let scaled = DecodeOptions { scale: Some(10.0), ..q15 };
+    assert_eq!(text(decode_packet(&[0x40, 0x00], &[DataType::I16], &scaled)), ["5"]);
+    assert_eq!(text(decode_packet(&1.5f32.to_be_bytes(), &[DataType::F32], &q15)), ["1.5"]);
+}
+
+#[test]
+fn detects_endianness_from_plausible_values() {
+    let options = DecodeOptions::default();
+    let little: [&[u8]; 3] = [&[1, 0, 2, 0], &[3, 0, 4, 0], &[5, 0, 6, 0]];
+    assert!(matches!(detect_endianness(&little, &[DataType::U16], &options), Endianness::Little));
+    let big: [&[u8]; 2] = [&20.5f32.to_be_bytes(), &21.0f32.to_be_bytes()];
+    assert!(matches!(detect_endianness(&big, &[DataType::F32], &options), Endianness::Big));
+}
+
+#[test]
+fn deframer_splits_length_prefixed_records() {
+    let mut deframer = Deframer::new(Framing::U16Length, 16);
+    assert_eq!(deframer.push(&[0, 2, 1, 2, 0, 1]), [vec![1, 2]]);
+    assert_eq!(deframer.push(&[3, 0, 0]), [vec![3], vec![]]);
+    // a length over the limit is skipped until the records line up again
+    assert_eq!(deframer.push(&[255, 255, 0, 1, 7]), [vec![7]]);
+    assert_eq!(deframer.push(&[0, 4, 1]), Vec::<Vec<u8>>::new());
+    assert_eq!(deframer.end_datagram(), (None, 3));
+    let mut none = Deframer::new(Framing::None, 16);
+    assert_eq!(none.push(&[0, 9]), [vec![0, 9]]);
+}
+
+#[test]
+fn deframer_aligns_records_to_sync_word() {
+    let mut deframer = Deframer::new(Framing::None, 16).with_sync_word(vec![0xaa, 0x55]);
+    // starts mid-record, ends in the first byte of a sync word
+    assert_eq!(deframer.push(&[1, 2, 0xaa, 0x55, 3, 4, 0xaa]), Vec::<Vec<u8>>::new());
+    assert_eq!(deframer.push(&[0x55, 5, 0xaa, 0x55, 6]), [vec![3, 4], vec![5]]);
+    assert_eq!(deframer.end_datagram(), (Some(vec![6]), 0));
+    assert_eq!(deframer.push(&[7, 8, 9]), Vec::<Vec<u8>>::new());
+    assert_eq!(deframer.end_datagram(), (None, 0));
+    assert_eq!(deframer.skipped(), 5);
+}
+
+#[test]
+fn deframer_cuts_records_of_a_fixed_length() {
+    let mut deframer = Deframer::new(Framing::None, 16).with_record_len(3);
+    assert_eq!(deframer.push(&[1, 2]), Vec::<Vec<u8>>::new());
+    assert_eq!(deframer.push(&[3, 4, 5, 6, 7]), [vec![1, 2, 3], vec![4, 5, 6]]);
+    assert_eq!(deframer.end_datagram(), (Some(vec![7]), 0));
+    assert_eq!(deframer.end_datagram(), (None, 0));
+}
+
+#[test]
+fn values_keep_their_type_until_formatted() {
+    let options = DecodeOptions::default();
+    let values = decode_packet(&[0xff, 0x01, 0x02], &[DataType::I8, DataType::U16], &options).unwrap();
+    assert_eq!(values, [Value::I8(-1), Value::U16(258)]);
+    assert_eq!(values[0].as_f64(), Some(-1.0));
+    let scaled = DecodeOptions { scale: Some(0.5), filter_max: Some(100.0), filter_mode: FilterMode::Blank, ..options };
+    let values = decode_packet(&[10, 250], &[DataType::U8], &scaled).unwrap();
+    assert_eq!(values, [Value::F64(5.0), Value::Filtered]);
+    assert_eq!(Value::Bool(true).to_string(), "1");
+    assert_eq!(Value::Filtered.as_f64(), None);
+}
+
+#[test]
+fn pad_records_completes_the_last_record() {
+    let options = DecodeOptions { pad_records: true, bool_bits: 2, ..DecodeOptions::default() };
+    let schema = [DataType::U8, DataType::U16, DataType::Bool];
+    assert_eq!(decode_row(&[1, 0], &schema, &options), Some(vec![Value::U8(1), Value::Missing, Value::Missing, Value::Missing]));
+    assert_eq!(decode_row(&[1, 0, 2, 3], &schema, &options), Some(vec![Value::U8(1), Value::U16(2), Value::Bool(true), Value::Bool(true)]));
+    assert_eq!(decode_row(&[], &schema, &options), Some(vec![]));
+}
+
+#[test]
+fn max_values_stops_decoding() {
+    let options = DecodeOptions { max_values: Some(3), ..DecodeOptions::default() };
+    assert_eq!(text(decode_packet(&[1, 2, 3, 4, 5], &[DataType::U8], &options)), ["1", "2", "3"]);
+    assert_eq!(text(decode_packet(&[0xff], &[DataType::Bool], &options)), ["1", "1", "1"]);
+    let all = DecodeOptions::default();
+    assert_eq!(value_count(5, &[DataType::U8], &all), 5);
+    assert_eq!(value_count(7, &[DataType::U8, DataType::I16], &all), 5);
+    assert_eq!(value_count(2, &[DataType::Bool], &all), 16);
+    assert_eq!(value_count(3, &[DataType::U8, DataType::Hex], &all), 2);
+}
+
+#[test]
+fn checksum_is_verified_and_stripped() {
+    assert_eq!(crc16(b"123456789"), 0x29b1);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(Checksum::Crc16.verify(&[1, 2, 0x0e, 0x7c], Endianness::Big), Some(&[1, 2][..]));
+    assert_eq!(Checksum::Crc16.verify(&[1, 2, 0x7c, 0x0e], Endianness::Big), None);
+    assert_eq!(Checksum::Crc16.verify(&[1, 2, 0x7c, 0x0e], Endianness::Little), Some(&[1, 2][..]));
+    assert_eq!(Checksum::Crc32.verify(&[1, 2, 0x92, 0x42, 0xcc, 0xb6], Endianness::Little), Some(&[1, 2][..]));
+    assert_eq!(Checksum::Crc32.verify(&[1, 2, 3], Endianness::Big), None);
+    assert_eq!(Checksum::None.verify(&[1, 2], Endianness::Big), Some(&[1, 2][..]));
+}
+
+#[test]
+fn value_type_follows_the_repeated_schema() {
+    let options = DecodeOptions { bool_bits: 2, ..DecodeOptions::default() };
+    let schema = [DataType::U8, DataType::Bool];
+    let types: Vec<String> = (0..7).map(|index| options.value_type(&schema, index).to_string()).collect();
+    assert_eq!(types, ["u8", "bool", "bool", "u8", "bool", "bool", "u8"]);
+}
+
+#[test]
+fn recv_errors_back_off_and_give_up() {
+    let mut errors = RecvErrors::new(10);
+    assert_eq!(errors.error(), Some(RECV_ERROR_INTERVAL));
+    assert_eq!(errors.error(), Some(RECV_ERROR_INTERVAL * 2));
+    errors.packet();
+    assert_eq!(errors.error(), Some(RECV_ERROR_INTERVAL));
+    let intervals: Vec<_> = (0..8).map_while(|_| errors.error()).collect();
+    assert_eq!(intervals.len(), 8);
+    assert_eq!(intervals.last(), Some(&MAX_RECV_ERROR_INTERVAL));
+    assert_eq!(errors.error(), None);
+    assert_eq!(errors.in_a_row(), 10);
+}